@@ -1,11 +1,44 @@
 use std::ptr::{self, NonNull};
+use std::alloc::{self, Layout};
+use std::mem::{self, ManuallyDrop};
 use std::marker::PhantomData;
-use std::cell::UnsafeCell;
-use std::rc::Rc;
-use std::ops::{Deref, DerefMut};
+use std::cell::{Ref, RefCell, RefMut, UnsafeCell};
+use std::rc::{self, Rc};
+use std::any::{Any, TypeId};
+use std::collections::{BTreeMap, HashMap, HashSet, LinkedList, VecDeque};
+use std::collections::hash_map::Entry;
+use std::ops::{Deref, DerefMut, Range};
+use std::pin::Pin;
+use std::fmt;
+use std::cmp::Ordering as CmpOrdering;
+use std::hash::{Hash, Hasher};
+use std::iter::FromIterator;
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+// So the derive's ::tracing_gc paths resolve in this crate's own tests, too.
+extern crate self as tracing_gc;
+
+// #[derive(Trace)] traces every field, so e.g. a dynamic language's
+//     enum Value { Int(i64), Str(Gc<str>), List(Gc<Vec<Value>>) }
+// needs nothing more than the derive (and Arena::gc_str for the strings).
+pub use tracing_gc_derive::Trace;
+
+// With the serde feature, Gc<T> is Serialize and Deserialize, sharing and cycles included; see
+// serialize_graph and deserialize_graph.
+#[cfg(feature = "serde")]
+mod serde_graph;
+#[cfg(feature = "serde")]
+pub use serde_graph::{deserialize_graph, serialize_graph};
 
 pub struct Visitor {
     _not_pub_constructable: (),
+    cancel: *const AtomicBool,  // null unless the collection is cancellable
+    // Marked boxes whose children haven't been traced yet. Explicit, rather than the call stack,
+    // so deep object graphs can't overflow it.
+    worklist: RefCell<Vec<NonNull<GcBox<()>>>>,
+    recording: bool,  // just list each child on the worklist, without marking anything
 }
 
 pub trait Trace {
@@ -20,16 +53,150 @@ pub trait Traverse: Sealed {
     fn marked(&self) -> bool;
     fn next(&self) -> GcPtr;
     fn prev(&self) -> GcPtr;
+    fn generation(&self) -> u64;
 }
 
 pub type GcPtr = *const dyn Traverse;
 pub type GcPtrNonNull = NonNull<dyn Traverse>;
 
 pub struct Arena {
+    id: u64,  // unique for the life of the program
     start: GcPtr,
     roots: Vec<GcPtrNonNull>,
+    // A secondary root set, kept separate so it can be dropped wholesale.
+    pinned: Vec<GcPtrNonNull>,
+    // Reachable for as long as the arena lives; nothing but clear removes these.
+    permanent: Vec<GcPtrNonNull>,
+    finalizers: HashMap<TypeId, Rc<Finalizer>>,
+    // Roots held by SharedGc handles, which register and release them without &mut Arena.
+    shared: Rc<RefCell<SharedRoots>>,
+    // Lent to each collection's Visitor and taken back, so its capacity carries over. Between
+    // steps of an incremental mark, it holds the gray objects: marked, but not yet traced.
+    worklist: Vec<NonNull<GcBox<()>>>,
+    incremental: bool,  // an incremental mark is underway
+    count_cycles: bool,  // whether sweeps count the cycles they free, for cycles_reclaimed
+    cycles_reclaimed: u64,
+    collections: u64,  // completed ones
+    last_collect_reason: Option<CollectReason>,
+    // Allocs of collected objects that Weaks still point at, kept for dead_weak_count until the
+    // Weaks are gone.
+    dead_allocs: Vec<Rc<dyn Any>>,
+    // Visits extra roots found at mark time, like a scan of a native stack.
+    root_scanner: Option<Box<RootScanner>>,
+    // Sees every box about to be freed, whatever its type.
+    on_unreachable: Option<Box<dyn FnMut(GcPtrNonNull)>>,
+    alloc_hook: Option<Box<dyn Fn(GcPtrNonNull, TypeId)>>,
+    timing: bool,
+    // Where the sweep notes the address of each box it frees, during collect_debug only.
+    freed_log: Option<Vec<*const ()>>,
+    sweep_yield: Option<(usize, Box<dyn Fn()>)>,
+    metrics: Option<Box<dyn MetricsSink>>,
+    // Collected boxes whose values haven't been dropped yet, when drops are deferred.
+    defer_drops: bool,
+    deferred_drops: Vec<NonNull<GcBox<()>>>,
+    ids: IdTable,
+    // What gc_with_drop_guard and on_finalize add, by generation, for the few objects with any.
+    // The sweep runs them when it reclaims the object, not when Gc handles drop.
+    drop_guards: HashMap<u64, Vec<Box<dyn FnOnce()>>>,
+    // Storage of freed boxes, by layout, for gc to reuse instead of allocating; at most POOL_LIMIT
+    // of them in all.
+    pool: HashMap<Layout, Vec<NonNull<u8>>>,
+    pool_len: usize,
+    // Collect on allocation once this many objects, or bytes, have been allocated since the last.
+    auto_collect: Option<usize>,
+    auto_collect_bytes: Option<usize>,
+    allocs_since_collection: usize,
+    bytes_since_collection: usize,
+    // With deferred rooting, root only flags the box; the next mark finds the flags and
+    // registers them.
+    deferred_rooting: bool,
+    pending_roots: usize,
+    // Set for the length of a collection, so that one started from inside it (say, by a
+    // finalizer or drop that gets at the arena) can do nothing instead of corrupting the list.
+    collecting: bool,
+    // Bumped by anything that could leave a live object unmarked--allocating, adopting, rooting--
+    // so sweep can tell whether the marks mark left are still good. marked_at is the count as of
+    // that mark, if the marks haven't been touched since.
+    changes: u64,
+    marked_at: Option<u64>,
+    // When the mark sweep is about to use began, for timing.
+    mark_started: Option<Instant>,
+    // Live objects by type, and in all, kept up to date by allocation and reclaim.
+    type_counts: HashMap<TypeId, usize>,
+    live_objects: usize,
+    live_bytes: usize,
+    // Hard caps for set_max_objects and set_max_bytes.
+    max_objects: Option<usize>,
+    max_bytes: Option<usize>,
+    // Keyed by copies, not the objects' own bytes, which go away with them.
+    interned: HashMap<Box<str>, Weak<str>>,
+    // A ValueInterner<T> per T, each holding at most value_intern_limit entries.
+    value_interners: HashMap<TypeId, Box<dyn Any>>,
+    value_intern_limit: usize,
+}
+
+// Weakly-held values by equality, each with when it was last used, for evicting the least
+// recently used.
+struct ValueInterner<T> {
+    entries: HashMap<T, (Weak<T>, u64)>,
+    clock: u64,
+}
+
+// Dense ids for the objects that asked for one. Freed slots are reused, with the tag bumped so
+// old ids into them stop resolving.
+#[derive(Default)]
+struct IdTable {
+    slots: Vec<IdSlot>,
+    free: Vec<u32>,
+    indices: HashMap<u64, u32>,  // by generation of the object in each slot
+}
+
+struct IdSlot {
+    ptr: Option<GcPtrNonNull>,
+    tag: u32,
+}
+
+#[derive(Default)]
+struct SharedRoots {
+    next_id: u64,
+    roots: HashMap<u64, GcPtrNonNull>,
+    // A SharedGc has been set since the last mark, which may not have seen its new target.
+    repointed: bool,
+}
+
+// A rooted Gc that can be cloned around freely and repointed without the arena at hand. The
+// root goes away when the last clone does.
+pub struct SharedGc<T: ?Sized> {
+    slot: Rc<SharedSlot<T>>,
+}
+
+struct SharedSlot<T: ?Sized> {
+    id: u64,
+    table: Rc<RefCell<SharedRoots>>,
+    gc: RefCell<Gc<T>>,
+}
+
+// Keeps an object from Arena::root_guard rooted until it's dropped. It holds the arena's shared
+// root table rather than the arena, so it's fine for it to outlive (or be moved past) the arena.
+#[must_use = "the object is unrooted as soon as the guard is dropped"]
+pub struct RootGuard {
+    id: u64,
+    table: Rc<RefCell<SharedRoots>>,
+}
+
+// In debug builds, what the sweep has freed so far, so that a corrupted list leading back to a
+// freed box panics instead of freeing it again. Free in release builds.
+#[derive(Default)]
+struct FreedSet {
+    #[cfg(debug_assertions)]
+    addrs: std::collections::HashSet<usize>,
 }
 
+// Type-erased finalizer; it knows its T and casts the box itself.
+type Finalizer = dyn Fn(NonNull<GcBox<()>>);
+
+type RootScanner = dyn FnMut(&Visitor);
+
 pub struct ArenaIter<'a> {
     cur: GcPtr,
     // Mark as referring to the Arena, even though we just chase internal pointers.
@@ -44,12 +211,37 @@ pub struct GcBox<T: ?Sized> {
     mark: bool,
     next: GcPtr,
     prev: GcPtr,
-    meta: *const (),  // vtable for &T as &dyn Trace
-    alloc: *mut GcAlloc<T>,  // point back to our alloc for dropping
+    trace: unsafe fn(NonNull<GcBox<()>>, &Visitor),  // trace the value as its real type
+    free: FreeShim,  // drop the box as its real type
+    detach: DetachShim,  // just mark the object collected, for freeing later (see deferred drops)
+    // Our alloc, held as long as we live so Weaks can upgrade even with no Gc<T>'s around. Only
+    // None before the first Gc<T> is made, and after the sweep takes it.
+    alloc: Option<Rc<UnsafeCell<GcAlloc<T>>>>,
+    generation: u64,  // unique per allocation, so a reused address can be told apart
+    // For slice values, the element count, to rebuild the fat pointer. For boxes from
+    // gc_with_layout and gc_boxed_trait, the allocation's alignment (its size is in size).
+    // Unused otherwise.
+    len: usize,
+    type_id: TypeId,
+    type_name: &'static str,
+    survived_collections: u32,
+    size: usize,  // of the whole box, since it can't be recovered from the header for unsized T
+    epoch: u64,  // how many collections the arena had finished when this was allocated
+    root_pending: bool,  // rooted, but not yet moved into the arena's roots (see deferred rooting)
+    root_index: Option<usize>,  // where it is in the arena's roots, if it's there
+    arena_id: u64,  // of the arena whose list it's on; 0 before threading and once detached
+    layout: Option<Layout>,  // of a plain Box allocation, whose storage can go in the pool
     value: T,
 }
 
+// repr(C) keeps arena_held first, where release_dead_alloc can find it without knowing T.
+#[repr(C)]
 pub struct GcAlloc<T: ?Sized> {
+    // Whether one of the strong counts is the arena's own--the box's, or dead_allocs'--rather
+    // than a Gc's.
+    arena_held: bool,
+    borrows: usize,  // GcRefs alive, through any handle
+    pinned: bool,  // by Gc::pin; nothing may move the value out any more
     inner: Option<NonNull<GcBox<T>>>,
 }
 
@@ -58,9 +250,241 @@ pub struct Gc<T: ?Sized> {
     marker: PhantomData<GcBox<T>>,
 }
 
+// Doesn't keep its object alive: once the object is collected, upgrade fails even if Gc handles
+// to it are still around.
+pub struct Weak<T: ?Sized> {
+    ptr: rc::Weak<UnsafeCell<GcAlloc<T>>>,
+}
+
+// A list of Weaks that drops dead entries as it's used, for observer-style registrations that
+// shouldn't keep their observers alive.
+pub struct WeakList<T: ?Sized> {
+    items: Vec<Weak<T>>,
+}
+
+// A singly-linked list with each node in the arena, pushed and popped at the front. Nodes can be
+// shared between lists, as with a cons list. Only what's reachable from a root survives: root
+// the head, or keep the list in something rooted.
+pub struct GcList<T> {
+    head: Option<Gc<ListNode<T>>>,
+    len: usize,
+}
+
+pub struct ListNode<T> {
+    value: T,
+    next: Option<Gc<ListNode<T>>>,
+}
+
+pub struct GcListIter<'a, T> {
+    cur: Option<&'a Gc<ListNode<T>>>,
+}
+
+// An identity key for an object that doesn't keep it alive. The generation distinguishes a box
+// from a later one that happens to land on the same address.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct WeakKey {
+    addr: usize,
+    generation: u64,
+}
+
+// A small integer naming an object, e.g. to index side arrays by. Doesn't keep it alive.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct ObjId {
+    index: u32,
+    tag: u32,
+}
+
+// A raw box pointer that remembers which allocation it referred to, so it can be checked before
+// use rather than silently aliasing whatever box later occupies the same address.
+#[derive(Clone, Copy, Debug)]
+pub struct GcHandle {
+    ptr: GcPtrNonNull,
+    generation: u64,
+}
+
+// A borrow of an object that Gc::replace can see, unlike Deref's: replace refuses while any is
+// alive, through whichever handle.
+pub struct GcRef<'a, T: ?Sized> {
+    value: &'a T,
+    alloc: &'a UnsafeCell<GcAlloc<T>>,
+}
+
+// A borrowed view of part of a Gc<[T]>. No new allocation; it can't outlive the handle.
+pub struct GcSlice<'a, T> {
+    slice: &'a [T],
+}
+
+// The root set at some point in time. Generation-tagged, so restoring it can't resurrect a root
+// whose object has since been freed (or whose address has been reused).
+#[derive(Clone, Debug)]
+pub struct RootsSnapshot {
+    roots: Vec<GcHandle>,
+}
+
+// The box for gc_with_trace_fn. repr(C) puts the value first, so a pointer to the whole is a
+// pointer to it.
+#[repr(C)]
+struct WithTraceFn<T> {
+    value: T,
+    trace_fn: fn(&T, &Visitor),
+}
+
+// A RefCell for values in the heap, so clones of one Gc can all mutate it, checked at runtime. A
+// collection while it's mutably borrowed can't look inside, so anything only it refers to then is
+// collected. During an incremental mark, storing references still needs Arena::write_barrier.
+pub struct GcCell<T: ?Sized> {
+    cell: RefCell<T>,
+}
+
+// Shares its value until written through, like Rc::make_mut: writing to a value that any other
+// Gc also points at clones it into a fresh allocation first.
+pub struct GcCow<T> {
+    gc: Gc<T>,
+}
+
+// Builds a tree bottom-up: begin opens a node, leaf and end add children to the innermost open
+// node, and finish makes the root. make turns a node's children into its value. Everything
+// allocated along the way stays rooted until finish.
+pub struct TreeBuilder<'a, T, F> {
+    arena: &'a mut Arena,
+    make: F,
+    open: Vec<Vec<Gc<T>>>,  // children so far of each open node, innermost last; the root's first
+    temps: Vec<Gc<T>>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ReplaceError {
+    Collected,
+    Borrowed,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BorrowError {
+    SameObject,
+    Collected,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ResourceError {
+    Collected,
+}
+
+// An OS handle (file, socket, ...) closed by close as soon as the sweep collects it, via a
+// finalizer, rather than whenever the box happens to be dropped. Make these with
+// Arena::gc_resource, which registers the finalizer.
+pub struct GcResource<H> {
+    handle: Option<H>,  // None once closed
+    close: fn(H),
+}
+
+// One object in a stable_walk. id is unique to the allocation for the life of the program, so
+// entries from different snapshots can be compared directly.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct StableObjectRef {
+    pub id: u64,
+    pub type_id: TypeId,
+    pub epoch: u64,
+}
+
+// The objects reachable at some point in time, by their stable ids, for reachability_diff.
+#[derive(Clone, Debug)]
+pub struct HeapSnapshot {
+    reachable: HashSet<u64>,
+}
+
+// Objects reachable now that weren't at the snapshot and have survived a collection since, so
+// aren't just temporaries: a nonempty one from a steady-state operation means a leak.
+#[derive(Clone, Debug)]
+pub struct ReachDiff {
+    pub new: Vec<StableObjectRef>,
+}
+
+impl ReachDiff {
+    pub fn is_empty(&self) -> bool {
+        self.new.is_empty()
+    }
+}
+
+// Everything the header knows about one object, for debuggers. strong_count counts Gc handles;
+// marked is only ever set between Arena::mark and Arena::sweep. rooted means by make_root or root
+// (not pinned, permanent, or shared).
+#[derive(Clone, Copy, Debug)]
+pub struct ObjectInfo {
+    pub type_id: TypeId,
+    pub type_name: &'static str,
+    pub size: usize,
+    pub epoch: u64,
+    pub generation: u64,
+    pub survived_collections: u32,
+    pub marked: bool,
+    pub rooted: bool,
+    pub strong_count: usize,
+}
+
+// The whole heap as plain data, for analyses of one's own. Nodes are in list order, and edges
+// are (from, to) indices into them, one per reference (so possibly repeated).
+#[derive(Clone, Debug)]
+pub struct ObjectGraph {
+    pub nodes: Vec<NodeInfo>,
+    pub edges: Vec<(usize, usize)>,
+}
+
+#[derive(Clone, Debug)]
+pub struct NodeInfo {
+    pub ptr: GcPtrNonNull,
+    pub type_id: TypeId,
+    pub type_name: &'static str,
+    pub size: usize,
+    pub root: bool,  // in any of the root sets
+}
+
 pub struct Collection {
     pub total: usize,
     pub collected: usize,
+    // Box sizes, headers included, of what was freed and what's left.
+    pub bytes_collected: usize,
+    pub bytes_live: usize,
+    pub timing: Option<CollectTiming>,  // only with Arena::set_timing
+    pub reason: CollectReason,
+}
+
+impl Collection {
+    fn reentrant(reason: CollectReason) -> Self {
+        Collection {
+            total: 0, collected: 0, bytes_collected: 0, bytes_live: 0, timing: None, reason,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CollectReason {
+    Manual,  // collect or collect_cancellable
+    Threshold,  // set_auto_collect_threshold
+    ByteThreshold,  // set_auto_collect_bytes
+    HeapLimit,  // set_max_objects or set_max_bytes
+}
+
+// From Arena::gc_try, when even a collection doesn't make room under the caps.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct HeapLimitExceeded;
+
+#[derive(Clone, Copy, Debug)]
+pub struct CollectTiming {
+    pub mark: Duration,
+    pub sweep: Duration,
+}
+
+// For exporting GC metrics. Every method defaults to doing nothing, so a sink only needs the ones
+// it cares about.
+pub trait MetricsSink {
+    fn on_alloc(&mut self, _type_id: TypeId) {}
+    fn on_free(&mut self, _type_id: TypeId) {}
+    fn on_collection(&mut self, _col: &Collection) {}
+}
+
+pub enum CollectResult {
+    Completed(Collection),
+    Cancelled,
 }
 
 fn null_gcptr() -> GcPtr {
@@ -74,235 +498,2947 @@ fn null_gcptr() -> GcPtr {
     }
 }
 
-fn extract_meta(t: &dyn Trace) -> *const () {
+// Zero is never handed out, so it can stand in for "no object".
+static NEXT_GENERATION: AtomicU64 = AtomicU64::new(1);
+
+fn next_generation() -> u64 {
+    NEXT_GENERATION.fetch_add(1, Ordering::Relaxed)
+}
+
+// Starts at 1 so 0 can mean no arena.
+static NEXT_ARENA_ID: AtomicU64 = AtomicU64::new(1);
+
+// Freed boxes an arena keeps for reuse, at most.
+const POOL_LIMIT: usize = 256;
+
+// List entries all carry the Traverse vtable of GcBox<()>. That's sound because Traverse only
+// touches the header, which is laid out identically for every T--and unlike coercing GcBox<T>
+// directly, it works when T is unsized. Anything that needs the value goes through the trace and
+// free shims in the header instead.
+fn erase<T: ?Sized>(ptr: NonNull<GcBox<T>>) -> GcPtrNonNull {
+    ptr.cast::<GcBox<()>>()
+}
+
+// The shims. These are monomorphized per T and stored in the box at construction, which is the
+// last point at which we know what T is.
+unsafe fn trace_value<T: Trace>(ptr: NonNull<GcBox<()>>, visitor: &Visitor) {
+    ptr.cast::<GcBox<T>>().as_ref().value.trace(visitor);
+}
+
+unsafe fn trace_nothing(_ptr: NonNull<GcBox<()>>, _visitor: &Visitor) {}
+
+// Drops the box as its real type, handing back its alloc if any Weaks to it are still around.
+type FreeShim = unsafe fn(NonNull<GcBox<()>>, Free) -> Option<Rc<dyn Any>>;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Free {
+    All,
+    KeepValue,  // it's been moved out (see Gc::try_unwrap)
+    KeepStorage,  // it's going in the pool; only for boxes with a layout
+}
+
+type DetachShim = unsafe fn(NonNull<GcBox<()>>) -> Option<Rc<dyn Any>>;
+
+// Null out the pointer to the box from its alloc, so all the Gc<T>'s pointing here know that the
+// allocation is gone, and let go of it--unless there are Weaks, which the arena keeps count of.
+fn detach_alloc<T: ?Sized + 'static>(alloc: &mut Option<Rc<UnsafeCell<GcAlloc<T>>>>) -> Option<Rc<dyn Any>> {
+    let alloc = alloc.take()?;
+    let keep = Rc::weak_count(&alloc) > 0;
+    unsafe {
+        // SAFETY: No references into the alloc outlive a call on a Gc<T>.
+        (*alloc.get()).inner = None;
+        (*alloc.get()).arena_held = keep;
+    }
+    if keep {
+        Some(alloc)
+    } else {
+        None
+    }
+}
+
+// For a detached alloc the arena is about to let go of.
+fn release_dead_alloc(alloc: &Rc<dyn Any>) {
     unsafe {
-        // SAFETY: as above.
-        // XXX Cast might be superfluous.
-        let (_ptr, meta): (*const (), *const ()) =
-            std::mem::transmute(t as *const dyn Trace);
-        meta
+        // SAFETY: It's an UnsafeCell<GcAlloc<T>> for some T, with arena_held at the front.
+        *(Rc::as_ptr(alloc) as *const UnsafeCell<bool> as *mut bool) = false;
+    }
+}
+
+// Detaches the alloc of a box handed out as Gc<T>s, leaving the box to be freed later. Like in
+// free_coerced, the header doesn't depend on the value type.
+unsafe fn detach_shim<T: ?Sized + 'static>(ptr: NonNull<GcBox<()>>) -> Option<Rc<dyn Any>> {
+    let alloc = ptr::addr_of_mut!((*ptr.as_ptr()).alloc) as *mut Option<Rc<UnsafeCell<GcAlloc<T>>>>;
+    detach_alloc(&mut *alloc)
+}
+
+unsafe fn free_value<T: 'static>(ptr: NonNull<GcBox<()>>, how: Free) -> Option<Rc<dyn Any>> {
+    let boxptr = ptr.cast::<GcBox<T>>().as_ptr();
+    let dead = detach_alloc(&mut (*boxptr).alloc);
+    free_box(boxptr, how);
+    dead
+}
+
+// For boxes allocated as a Box<GcBox<T>>.
+unsafe fn free_box<T>(boxptr: *mut GcBox<T>, how: Free) {
+    match how {
+        Free::All => drop(Box::from_raw(boxptr)),
+        Free::KeepValue => drop(Box::from_raw(boxptr as *mut GcBox<ManuallyDrop<T>>)),
+        Free::KeepStorage => ptr::drop_in_place(boxptr),
+    }
+}
+
+// For a box allocated as a T but handed out as Gc<U>s: the alloc is U's, though the rest of the
+// box is T's.
+unsafe fn free_coerced<T: 'static, U: ?Sized + 'static>(
+    ptr: NonNull<GcBox<()>>, how: Free,
+) -> Option<Rc<dyn Any>> {
+    let boxptr = ptr.cast::<GcBox<T>>().as_ptr();
+    // The field is in the header, so its offset doesn't depend on the value type, and an Rc is
+    // one pointer either way. Taking it leaves None, which Box::from_raw can drop as any type.
+    let alloc = ptr::addr_of_mut!((*boxptr).alloc) as *mut Option<Rc<UnsafeCell<GcAlloc<U>>>>;
+    let dead = detach_alloc(&mut *alloc);
+    free_box(boxptr, how);
+    dead
+}
+
+unsafe fn slice_box<T>(ptr: NonNull<GcBox<()>>) -> *mut GcBox<[T]> {
+    ptr::slice_from_raw_parts_mut(ptr.as_ptr() as *mut T, ptr.as_ref().len) as *mut GcBox<[T]>
+}
+
+unsafe fn trace_slice<T: Trace>(ptr: NonNull<GcBox<()>>, visitor: &Visitor) {
+    for item in &(*slice_box::<T>(ptr)).value {
+        item.trace(visitor);
+    }
+}
+
+// This is the layout the compiler computes for a repr(C) GcBox<[T]> of this length (the header,
+// padded to T's alignment, then the elements), which Box::from_raw in free_slice relies on. Also
+// returns where the elements start.
+fn slice_box_layout<T>(len: usize) -> (Layout, usize) {
+    let (layout, offset) = Layout::from_size_align(
+        mem::offset_of!(GcBox<[T; 0]>, value),
+        mem::align_of::<GcBox<[T; 0]>>(),
+    ).and_then(|header| header.extend(Layout::array::<T>(len)?))
+        .expect("Gc slice too large");
+    (layout.pad_to_align(), offset)
+}
+
+// For boxes allocated by hand, which can't be built with Gc::fresh_box. Everything else starts
+// out the same for every box.
+unsafe fn write_header<T: ?Sized + 'static>(
+    boxptr: *mut GcBox<T>, trace: unsafe fn(NonNull<GcBox<()>>, &Visitor), free: FreeShim,
+    type_id: TypeId, type_name: &'static str, len: usize, size: usize,
+) {
+    ptr::addr_of_mut!((*boxptr).mark).write(false);
+    ptr::addr_of_mut!((*boxptr).next).write(null_gcptr());
+    ptr::addr_of_mut!((*boxptr).prev).write(null_gcptr());
+    ptr::addr_of_mut!((*boxptr).trace).write(trace);
+    ptr::addr_of_mut!((*boxptr).free).write(free);
+    ptr::addr_of_mut!((*boxptr).detach).write(detach_shim::<T>);
+    ptr::addr_of_mut!((*boxptr).alloc).write(None);
+    ptr::addr_of_mut!((*boxptr).generation).write(next_generation());
+    ptr::addr_of_mut!((*boxptr).len).write(len);
+    ptr::addr_of_mut!((*boxptr).type_id).write(type_id);
+    ptr::addr_of_mut!((*boxptr).type_name).write(type_name);
+    ptr::addr_of_mut!((*boxptr).survived_collections).write(0);
+    ptr::addr_of_mut!((*boxptr).size).write(size);
+    ptr::addr_of_mut!((*boxptr).epoch).write(0);
+    ptr::addr_of_mut!((*boxptr).root_pending).write(false);
+    ptr::addr_of_mut!((*boxptr).root_index).write(None);
+    ptr::addr_of_mut!((*boxptr).arena_id).write(0);
+    ptr::addr_of_mut!((*boxptr).layout).write(None);
+}
+
+// Moves items into a new GcBox<[T]>, writing every header field. The shims and type are left to
+// the caller, since the box may be reinterpreted (as a str, say).
+unsafe fn alloc_slice_box<T: 'static>(
+    mut items: Vec<T>, trace: unsafe fn(NonNull<GcBox<()>>, &Visitor),
+    free: FreeShim, type_id: TypeId, type_name: &'static str,
+) -> *mut GcBox<[T]> {
+    let len = items.len();
+    let (layout, offset) = slice_box_layout::<T>(len);
+    // SAFETY: The header is never zero-sized, so neither is the layout. Every field is written
+    // before anything reads the box.
+    let raw = alloc::alloc(layout);
+    if raw.is_null() {
+        alloc::handle_alloc_error(layout);
+    }
+    let boxptr = ptr::slice_from_raw_parts_mut(raw as *mut T, len) as *mut GcBox<[T]>;
+    write_header(boxptr, trace, free, type_id, type_name, len, layout.size());
+    // Move the elements out of the Vec; it keeps (and frees) only its buffer.
+    ptr::copy_nonoverlapping(items.as_ptr(), raw.add(offset) as *mut T, len);
+    items.set_len(0);
+    boxptr
+}
+
+unsafe fn free_slice<T: 'static>(ptr: NonNull<GcBox<()>>, how: Free) -> Option<Rc<dyn Any>> {
+    debug_assert_ne!(how, Free::KeepStorage, "slice boxes don't go in the pool");
+    let boxptr = slice_box::<T>(ptr);
+    let dead = detach_alloc(&mut (*boxptr).alloc);
+    if how == Free::All {
+        drop(Box::from_raw(boxptr));
+    } else {
+        drop(Box::from_raw(slice_box::<ManuallyDrop<T>>(ptr)));
+    }
+    dead
+}
+
+// There's nothing to drop in a str anyway.
+unsafe fn free_str(ptr: NonNull<GcBox<()>>, how: Free) -> Option<Rc<dyn Any>> {
+    debug_assert_ne!(how, Free::KeepStorage, "str boxes don't go in the pool");
+    let boxptr = slice_box::<u8>(ptr) as *mut GcBox<str>;
+    let dead = detach_alloc(&mut (*boxptr).alloc);
+    drop(Box::from_raw(boxptr));
+    dead
+}
+
+// How far into an allocation aligned to align a GcBox<T> has to start for its value to be aligned
+// to align too. The value's offset is a multiple of the box's alignment, so this is as well.
+fn aligned_value_pad<T>(align: usize) -> usize {
+    let offset = mem::offset_of!(GcBox<T>, value);
+    (align - offset % align) % align
+}
+
+// For boxes from gc_with_layout, which keep the allocation's alignment in len and its size in
+// size.
+unsafe fn free_aligned<T: 'static>(ptr: NonNull<GcBox<()>>, how: Free) -> Option<Rc<dyn Any>> {
+    debug_assert_ne!(how, Free::KeepStorage, "aligned boxes don't go in the pool");
+    let boxptr = ptr.cast::<GcBox<T>>().as_ptr();
+    let dead = detach_alloc(&mut (*boxptr).alloc);
+    let layout = Layout::from_size_align_unchecked((*boxptr).size, (*boxptr).len);
+    let raw = (boxptr as *mut u8).sub(aligned_value_pad::<T>(layout.align()));
+    if how == Free::All {
+        ptr::drop_in_place(boxptr);
+    } else {
+        ptr::drop_in_place(boxptr as *mut GcBox<ManuallyDrop<T>>);
+    }
+    alloc::dealloc(raw, layout);
+    dead
+}
+
+// A box from gc_boxed_trait starts this far into its allocation (aligned to align), just after
+// the fat pointer to it, which its shims need and can't rebuild from the thin one they're given.
+// It keeps the allocation's alignment in len and its size in size, like an aligned box.
+fn boxed_trait_offset(align: usize) -> usize {
+    mem::size_of::<*mut GcBox<dyn Trace>>().next_multiple_of(align)
+}
+
+unsafe fn boxed_trait_box(ptr: NonNull<GcBox<()>>) -> *mut GcBox<dyn Trace> {
+    *(ptr.as_ptr() as *const *mut GcBox<dyn Trace>).sub(1)
+}
+
+unsafe fn trace_boxed_trait(ptr: NonNull<GcBox<()>>, visitor: &Visitor) {
+    (*boxed_trait_box(ptr)).value.trace(visitor);
+}
+
+// The value can't be moved out of a dyn Trace (try_unwrap needs a T), so it always goes too.
+unsafe fn free_boxed_trait(ptr: NonNull<GcBox<()>>, how: Free) -> Option<Rc<dyn Any>> {
+    debug_assert_eq!(how, Free::All, "boxed trait objects are only freed whole");
+    let boxptr = boxed_trait_box(ptr);
+    let dead = detach_alloc(&mut (*boxptr).alloc);
+    let layout = Layout::from_size_align_unchecked((*boxptr).size, (*boxptr).len);
+    let raw = (boxptr as *mut u8).sub(boxed_trait_offset(layout.align()));
+    ptr::drop_in_place(boxptr);
+    alloc::dealloc(raw, layout);
+    dead
+}
+
+// Points a fat pointer at data instead, keeping its metadata. There's no putting one together on
+// stable (ptr::from_raw_parts), so this relies on the address being the first word of a fat
+// pointer, as it always has been; the debug_assert catches a compiler where it isn't.
+unsafe fn with_address<T: ?Sized>(mut fat: *mut T, data: *mut u8) -> *mut T {
+    let address = ptr::addr_of_mut!(fat) as *mut *mut u8;
+    debug_assert_eq!(*address, fat as *mut u8);
+    *address = data;
+    fat
+}
+
+// Tarjan's algorithm, iteratively, counting only the components that contain a cycle (more than
+// one node, or a node with an edge to itself).
+fn count_cycles(edges: &[Vec<usize>]) -> u64 {
+    const UNVISITED: usize = usize::MAX;
+    let mut index = vec![UNVISITED; edges.len()];
+    let mut low = vec![0; edges.len()];
+    let mut on_stack = vec![false; edges.len()];
+    let mut stack = Vec::new();
+    let mut next = 0;
+    let mut cycles = 0;
+    for start in 0..edges.len() {
+        if index[start] != UNVISITED {
+            continue;
+        }
+        // Frames of (node, next edge to follow), standing in for the recursion.
+        let mut calls = vec![(start, 0)];
+        index[start] = next;
+        low[start] = next;
+        next += 1;
+        stack.push(start);
+        on_stack[start] = true;
+        while let Some(frame) = calls.last_mut() {
+            let v = frame.0;
+            if let Some(&w) = edges[v].get(frame.1) {
+                frame.1 += 1;
+                if index[w] == UNVISITED {
+                    index[w] = next;
+                    low[w] = next;
+                    next += 1;
+                    stack.push(w);
+                    on_stack[w] = true;
+                    calls.push((w, 0));
+                } else if on_stack[w] {
+                    low[v] = low[v].min(index[w]);
+                }
+                continue;
+            }
+            calls.pop();
+            if let Some(&(u, _)) = calls.last() {
+                low[u] = low[u].min(low[v]);
+            }
+            if low[v] == index[v] {
+                let mut size = 0;
+                loop {
+                    let w = stack.pop().unwrap();
+                    on_stack[w] = false;
+                    size += 1;
+                    if w == v {
+                        break;
+                    }
+                }
+                if size > 1 || edges[v].contains(&v) {
+                    cycles += 1;
+                }
+            }
+        }
     }
+    cycles
 }
 
 impl Arena {
     pub fn new() -> Self {
         Self {
+            id: NEXT_ARENA_ID.fetch_add(1, Ordering::Relaxed),
             start: null_gcptr(),
             roots: Vec::new(),
+            pinned: Vec::new(),
+            permanent: Vec::new(),
+            finalizers: HashMap::new(),
+            shared: Rc::new(RefCell::new(SharedRoots::default())),
+            worklist: Vec::new(),
+            incremental: false,
+            count_cycles: false,
+            cycles_reclaimed: 0,
+            collections: 0,
+            last_collect_reason: None,
+            dead_allocs: Vec::new(),
+            root_scanner: None,
+            on_unreachable: None,
+            alloc_hook: None,
+            timing: false,
+            freed_log: None,
+            sweep_yield: None,
+            metrics: None,
+            defer_drops: false,
+            deferred_drops: Vec::new(),
+            ids: IdTable::default(),
+            drop_guards: HashMap::new(),
+            pool: HashMap::new(),
+            pool_len: 0,
+            auto_collect: None,
+            auto_collect_bytes: None,
+            allocs_since_collection: 0,
+            bytes_since_collection: 0,
+            deferred_rooting: false,
+            pending_roots: 0,
+            collecting: false,
+            changes: 0,
+            marked_at: None,
+            mark_started: None,
+            type_counts: HashMap::new(),
+            live_objects: 0,
+            live_bytes: 0,
+            max_objects: None,
+            max_bytes: None,
+            interned: HashMap::new(),
+            value_interners: HashMap::new(),
+            value_intern_limit: 256,
         }
     }
 
-    pub fn gc<T: Trace + 'static>(&mut self, value: T) -> Gc<T> {
-        let gc = Gc::new(value);
+    // Push a freshly-constructed box onto the front of the list.
+    fn thread<T: ?Sized>(&mut self, gc: &Gc<T>) {
         unsafe {
             // SAFETY: We're confident that this freshly-constructed Gc contains a unique, new
             // allocation (by Box) to a GcBox.
-            let pt = (*gc.ptr.get()).inner.unwrap().as_ptr();
-            if let Some(gcbox) = (self.start as *mut GcBox<()>).as_mut() {
-                gcbox.prev = pt;
-            }
-            (*gc.ptr.get()).inner.unwrap().as_mut().next = self.start;
-            self.start = pt;
+            let pt = erase((*gc.ptr.get()).inner.unwrap());
+            self.thread_box(pt);
+            self.allocated(pt);
+            self.auto_collect(&[pt]);
         }
-        gc
     }
-    
-    pub fn root<T: Trace + 'static>(&mut self, value: T) -> Gc<T> {
-        let gc = self.gc(value);
-        unsafe {
-            // SAFETY: Most of the worry here is just dereferencing the UnsafeCell. Since the
-            // interior type is Copy, this should be fine.
-            self.roots.push((*gc.ptr.get()).inner.unwrap());
+
+    // Bookkeeping for a box newly threaded onto the list.
+    unsafe fn allocated(&mut self, pt: GcPtrNonNull) {
+        let gcbox = pt.cast::<GcBox<()>>().as_mut();
+        gcbox.epoch = self.collections;
+        if self.incremental {
+            // Allocated gray: it can only hold references the mutator already had, which the mark
+            // might not have reached yet.
+            gcbox.mark = true;
+            self.worklist.push(pt.cast());
+        }
+        gcbox.arena_id = self.id;
+        self.count(gcbox.type_id, gcbox.size);
+        self.allocs_since_collection += 1;
+        self.bytes_since_collection += gcbox.size;
+        if let Some(hook) = &self.alloc_hook {
+            hook(pt, gcbox.type_id);
+        }
+        if let Some(sink) = &mut self.metrics {
+            sink.on_alloc(gcbox.type_id);
         }
-        gc
     }
 
-    pub fn make_root<T: 'static>(&mut self, gc: &Gc<T>) {
-        let inner = unsafe {
-            // SAFETY: as above, this is mostly because we're using an UnsafeCell. No ref is coined
-            // here, so we're not making any promises about lifetimes we can't keep.
-            (*gc.ptr.get()).inner
+    // Collects if a threshold's been reached. The fresh objects aren't referenced by anything yet,
+    // so they're held for the duration.
+    fn auto_collect(&mut self, fresh: &[GcPtrNonNull]) {
+        let reason = if self.allocs_until_collection() == Some(0) {
+            CollectReason::Threshold
+        } else if self.bytes_until_collection() == Some(0) {
+            CollectReason::ByteThreshold
+        } else {
+            return;
         };
-        // FIXME: this is still slow
-        if let Some(inner) = inner {  // NB the shadow
-            if ! self.roots.iter().any(|p| {
-                std::ptr::eq(p.as_ptr(), inner.as_ptr())
-            }) {  // Avoid duplicates in the roots
-                self.roots.push(inner);
-            }
-        }
+        let pinned = self.pinned.len();
+        self.pinned.extend_from_slice(fresh);
+        self.collect_for(reason);
+        self.pinned.truncate(pinned);
     }
 
-    pub fn unroot<T>(&mut self, gc: &Gc<T>) {
-        // FIXME: This is expected to be a cold path
-        self.roots = self.roots.iter().cloned()
-            .filter(|ptr| !ptr::eq(ptr.as_ptr() as *const _, unsafe { *gc.ptr.get() }.inner.unwrap().as_ptr() as *const _))
-            .collect();
+    // The box must not be on any list.
+    unsafe fn thread_box(&mut self, pt: GcPtrNonNull) {
+        self.changes += 1;
+        if let Some(gcbox) = (self.start as *mut GcBox<()>).as_mut() {
+            gcbox.prev = pt.as_ptr();
+        }
+        let gcbox = pt.cast::<GcBox<()>>().as_mut();
+        gcbox.prev = null_gcptr();
+        gcbox.next = self.start;
+        self.start = pt.as_ptr();
     }
 
-    pub fn iter<'s>(&'s mut self) -> ArenaIter<'s> {
-        ArenaIter {
-            cur: self.start,
-            marker: PhantomData,
+    pub fn gc<T: Trace + 'static>(&mut self, value: T) -> Gc<T> {
+        match self.gc_try(value) {
+            Ok(gc) => gc,
+            Err(err) => panic!("{}", err),
         }
     }
 
-    pub fn collect(&mut self) -> Collection {
-        let mut col = Collection {
-            total: 0, collected: 0,
+    // Like gc, but fails (dropping the value) instead of panicking if it would put the heap over
+    // a cap.
+    pub fn gc_try<T: Trace + 'static>(&mut self, value: T) -> Result<Gc<T>, HeapLimitExceeded> {
+        self.make_room(1, mem::size_of::<GcBox<T>>())?;
+        let gc = match self.take_pooled(Layout::new::<GcBox<T>>()) {
+            Some(slot) => unsafe {
+                // SAFETY: The slot was a Box allocation of this very layout, so it can hold (and
+                // later be freed as) a Box<GcBox<T>>.
+                let boxptr = slot.cast::<GcBox<T>>().as_ptr();
+                boxptr.write(Gc::fresh_box(value, free_value::<T>));
+                Gc::from_box(boxptr)
+            },
+            None => Gc::new(value),
         };
-        for mut t in self.iter() {
-            unsafe {
-                // SAFETY: We expect these to have been already constructed and aligned normally,
-                // and this iterator--strictly speaking--returns only non-null pointers.
-                t.as_mut().unmark();
-            }
-            col.total += 1;
-        }
-        let visitor = Visitor {
-            _not_pub_constructable: (),
-        };
-        // Strictly speaking, we don't mutate the _values_ in this list, but we do mutate their
-        // referents through the underlying raw pointer.
-        for r in &mut self.roots {
-            unsafe {
-                // SAFETY: By virtue of this very line, the roots list cannot be left with dangling
-                // pointers (as all member objects are marked).
-                (*r).as_mut().mark();
-            }
-            // With that mut borrow out of scope, do the recursive trace
-            unsafe {
-                // SAFETY: Begin your dragon prayers.
-                // We've sealed Traverse as a trait, so we know our implementors (and it's only
-                // GcBox).
-                // This cast intentionally discards the Traverse vtable--we won't need it again.
-                // The type we chose for T is definitely wrong, but we make up for it by manually
-                // assembling the fat pointer.
-                let gcbox = r.cast::<GcBox<()>>().as_ref();
-                let tobj: &dyn Trace = std::mem::transmute(
-                    (&gcbox.value, gcbox.meta)
-                );
-                tobj.trace(&visitor);
-            }
-        }
-        let mut start = self.start;
-        for t in self.iter() {
-            if ! unsafe { t.as_ref().marked() } {
+        self.thread(&gc);
+        Ok(gc)
+    }
+
+    fn take_pooled(&mut self, layout: Layout) -> Option<NonNull<u8>> {
+        let slot = self.pool.get_mut(&layout)?.pop()?;
+        self.pool_len -= 1;
+        Some(slot)
+    }
+
+    // How many freed boxes' storage is being kept for reuse.
+    pub fn pool_len(&self) -> usize {
+        self.pool_len
+    }
+
+    // Gives all the pooled storage back to the allocator, as under memory pressure.
+    pub fn release_pool(&mut self) {
+        for (layout, slots) in self.pool.drain() {
+            for slot in slots {
                 unsafe {
-                    let boxptr = t.cast::<GcBox<()>>();
-                    // TODO XXX: Unthread pointers in gcbox and change self.start if need be
-                    if ptr::eq(start, t.as_ptr()) {
-                        start = boxptr.as_ref().next;
-                    }
-                    {
-                        let boxmut = boxptr.as_ptr().as_mut().unwrap();
-                        if let Some(prevbox) = (boxmut.prev as *mut GcBox<()>).as_mut() {
-                            prevbox.next = boxmut.next;
-                        }
-                        if let Some(nextbox) = (boxmut.next as *mut GcBox<()>).as_mut() {
-                            nextbox.prev = boxmut.prev;
-                        }
-                    }
-                    // Null out the pointer to the box from its alloc, so all the Gc<T>'s pointing
-                    // here know that the allocation is gone.
-                    (*t.cast::<GcBox<()>>().as_mut().alloc).inner = None;
-                    // Collect the box again and let it drop
-                    Box::from_raw(t.as_ptr());
+                    // SAFETY: Pooled storage is a Box allocation of its layout, with nothing in it.
+                    alloc::dealloc(slot.as_ptr(), layout);
                 }
-                col.collected += 1;
             }
         }
-        self.start = start;
-        col
+        self.pool_len = 0;
     }
-}
-
-impl<'s> IntoIterator for &'s mut Arena {
-    type Item = GcPtrNonNull;
-    type IntoIter = ArenaIter<'s>;
-    fn into_iter(self) -> Self::IntoIter {
-        self.iter()
-    }
-}
 
-// Note the use of associated methods because of Deref<Target=T>.
-impl<T: ?Sized> Gc<T> {
-    pub fn try_as_ref(this: &Self) -> Option<&T> {
-        unsafe {
-            // SAFETY: we're bounding the reference implicitly with the lifetime on self.
-            (*this.ptr.get()).inner.map(|pr| {
-                &pr.as_ref().value
-            })
+    // Collects first if allocating this many more objects, of this many bytes in all, would go
+    // over a cap, and fails if that doesn't bring it back under.
+    fn make_room(&mut self, objects: usize, bytes: usize) -> Result<(), HeapLimitExceeded> {
+        let fits = |arena: &Arena| {
+            arena.max_objects.is_none_or(|n| arena.live_objects + objects <= n)
+                && arena.max_bytes.is_none_or(|b| arena.live_bytes + bytes <= b)
+        };
+        if fits(self) {
+            return Ok(());
         }
+        self.collect_for(CollectReason::HeapLimit);
+        if fits(self) { Ok(()) } else { Err(HeapLimitExceeded) }
     }
 
-    pub fn as_ref(this: &Self) -> &T {
-        Self::try_as_ref(this).expect("Gc::as_ref on collected object")
+    // make_room, for the allocators that panic like gc instead of failing like gc_try.
+    fn need_room(&mut self, objects: usize, bytes: usize) {
+        if let Err(err) = self.make_room(objects, bytes) {
+            panic!("{}", err);
+        }
     }
 
-    pub fn try_as_mut(this: &mut Self) -> Option<&mut T> {
+    // The new objects go on the front of the list together, in the order given.
+    pub fn gc_batch<T: Trace + 'static>(&mut self, values: Vec<T>) -> Vec<Gc<T>> {
+        self.need_room(values.len(), values.len() * mem::size_of::<GcBox<T>>());
+        let gcs: Vec<Gc<T>> = values.into_iter().map(Gc::new).collect();
+        let boxes: Vec<_> = gcs.iter().map(|gc| unsafe {
+            // SAFETY: Freshly allocated, so certainly live.
+            (*gc.ptr.get()).inner.unwrap().cast::<GcBox<()>>()
+        }).collect();
+        let (first, last) = match (boxes.first(), boxes.last()) {
+            (Some(&first), Some(&last)) => (first, last),
+            _ => return gcs,
+        };
         unsafe {
-            // SAFETY: As above; note the mutable borrow of self to statically guarantee
-            // uniqueness.
-            (*this.ptr.get()).inner.map(|mut pr| {
-                &mut pr.as_mut().value
-            })
+            // Link the run up among itself, then splice it in all at once.
+            for pair in boxes.windows(2) {
+                let (mut a, mut b) = (pair[0], pair[1]);
+                a.as_mut().next = erase(b).as_ptr();
+                b.as_mut().prev = erase(a).as_ptr();
+            }
+            if let Some(old) = (self.start as *mut GcBox<()>).as_mut() {
+                old.prev = erase(last).as_ptr();
+            }
+            (*last.as_ptr()).next = self.start;
+            self.start = erase(first).as_ptr();
+            for &gcbox in &boxes {
+                self.allocated(erase(gcbox));
+            }
         }
+        self.auto_collect(&boxes.into_iter().map(erase).collect::<Vec<_>>());
+        gcs
     }
 
-    pub fn as_mut(this: &mut Self) -> &mut T {
-        Self::try_as_mut(this).expect("Gc::as_mut on collected object")
+    pub fn gc_vec_with_capacity<T: Trace + 'static>(&mut self, cap: usize) -> Gc<Vec<T>> {
+        self.gc(Vec::with_capacity(cap))
     }
 
-    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+    pub fn gc_slice<T: Trace + 'static>(&mut self, items: impl IntoIterator<Item = T>) -> Gc<[T]> {
+        let items: Vec<T> = items.into_iter().collect();
+        self.need_room(1, slice_box_layout::<T>(items.len()).0.size());
+        let gc = Gc::new_slice(items);
+        self.thread(&gc);
+        gc
+    }
+
+    // Moves the value out of the Box and into an object of its own, vtable and all. Its concrete
+    // type is lost to the Box, so type queries see it as a dyn Trace.
+    pub fn gc_boxed_trait(&mut self, value: Box<dyn Trace>) -> Gc<dyn Trace> {
+        let value_layout = Layout::for_value(&*value);
+        // What the compiler computes for a repr(C) GcBox<dyn Trace> holding this value.
+        let (box_layout, value_offset) = Layout::from_size_align(
+            mem::offset_of!(GcBox<()>, value),
+            mem::align_of::<GcBox<()>>(),
+        ).and_then(|header| header.extend(value_layout)).expect("value too large");
+        let box_layout = box_layout.pad_to_align();
+        let offset = boxed_trait_offset(box_layout.align());
+        let alloc_layout = Layout::from_size_align(offset + box_layout.size(), box_layout.align())
+            .expect("value too large");
+        self.need_room(1, alloc_layout.size());
+        let gc = unsafe {
+            // SAFETY: The layout isn't zero-sized, and the box after the fat pointer is aligned.
+            // Every field is written before anything reads the box. The value's bytes are moved,
+            // and the Box's storage freed without dropping them.
+            let raw = alloc::alloc(alloc_layout);
+            if raw.is_null() {
+                alloc::handle_alloc_error(alloc_layout);
+            }
+            let old = Box::into_raw(value);
+            let boxptr = with_address(old as *mut GcBox<dyn Trace>, raw.add(offset));
+            write_header(
+                boxptr, trace_boxed_trait, free_boxed_trait,
+                TypeId::of::<dyn Trace>(), std::any::type_name::<dyn Trace>(),
+                alloc_layout.align(), alloc_layout.size(),
+            );
+            (raw.add(offset) as *mut *mut GcBox<dyn Trace>).sub(1).write(boxptr);
+            let dest = raw.add(offset + value_offset);
+            debug_assert_eq!(ptr::addr_of!((*boxptr).value) as *const u8, dest as *const u8);
+            ptr::copy_nonoverlapping(old as *const u8, dest, value_layout.size());
+            if value_layout.size() != 0 {
+                alloc::dealloc(old as *mut u8, value_layout);
+            }
+            Gc::from_box(boxptr)
+        };
+        self.thread(&gc);
+        gc
+    }
+
+    /// Allocates `value` as a `T`, but hands back a `Gc<U>` for some unsized `U` (such as a
+    /// `dyn Trait`) that `T` coerces to. Prefer the `gc_dyn!` macro, which supplies `coerce`.
+    ///
+    /// # Safety
+    ///
+    /// `coerce` must return its argument, only unsized: `|p| p`, where the compiler knows how.
+    pub unsafe fn gc_unsized<T: Trace + 'static, U: ?Sized + 'static>(
+        &mut self, value: T, coerce: fn(*mut T) -> *mut U,
+    ) -> Gc<U> {
+        self.need_room(1, mem::size_of::<GcBox<T>>());
+        let gc = Gc::new_unsized(value, coerce);
+        self.thread(&gc);
+        gc
+    }
+
+    // For types that can't implement Trace, such as other crates' (the orphan rule): trace_fn
+    // visits whatever Gcs the value holds.
+    pub fn gc_with_trace_fn<T: 'static>(&mut self, value: T, trace_fn: fn(&T, &Visitor)) -> Gc<T> {
+        // The header is already aligned for a fn pointer, so wrapping the value doesn't move it.
+        debug_assert_eq!(
+            mem::offset_of!(GcBox<WithTraceFn<T>>, value),
+            mem::offset_of!(GcBox<T>, value),
+        );
+        self.need_room(1, mem::size_of::<GcBox<WithTraceFn<T>>>());
+        let gc: Gc<T> = Gc::new_unsized(WithTraceFn { value, trace_fn }, |p| p as *mut T);
+        unsafe {
+            // SAFETY: Freshly allocated, so certainly live. Finalizers and type counts should see
+            // it as the T it's handed out as.
+            let gcbox = (*gc.ptr.get()).inner.unwrap().as_mut();
+            gcbox.type_id = TypeId::of::<T>();
+            gcbox.type_name = std::any::type_name::<T>();
+        }
+        self.thread(&gc);
+        gc
+    }
+
+    // Moves the value out of rc if this is the only reference to it; otherwise clones it, and the
+    // other Rcs go on sharing the original, separately from the new object.
+    pub fn gc_from_rc<T: Trace + Clone + 'static>(&mut self, rc: Rc<T>) -> Gc<T> {
+        self.gc(Rc::try_unwrap(rc).unwrap_or_else(|rc| T::clone(&rc)))
+    }
+
+    // Allocates with layout's alignment for the value, e.g. for SIMD. layout must be big enough
+    // for the whole box; any padding needed to align the value is added on top.
+    pub fn gc_with_layout<T: Trace + 'static>(&mut self, value: T, layout: Layout) -> Gc<T> {
+        assert!(
+            layout.size() >= mem::size_of::<GcBox<T>>() && layout.align() >= mem::align_of::<GcBox<T>>(),
+            "layout can't hold a GcBox<{}>", std::any::type_name::<T>(),
+        );
+        let pad = aligned_value_pad::<T>(layout.align());
+        let alloc_layout = Layout::from_size_align(layout.size() + pad, layout.align())
+            .expect("layout too large");
+        self.need_room(1, alloc_layout.size());
+        let gc = unsafe {
+            // SAFETY: The layout isn't zero-sized, and the box fits after the pad, which keeps it
+            // aligned. Every field is written before anything reads the box.
+            let raw = alloc::alloc(alloc_layout);
+            if raw.is_null() {
+                alloc::handle_alloc_error(alloc_layout);
+            }
+            let boxptr = raw.add(pad) as *mut GcBox<T>;
+            let mut gcbox = Gc::fresh_box(value, free_aligned::<T>);
+            gcbox.len = alloc_layout.align();
+            gcbox.size = alloc_layout.size();
+            gcbox.layout = None;
+            boxptr.write(gcbox);
+            Gc::from_box(boxptr)
+        };
+        self.thread(&gc);
+        gc
+    }
+
+    pub fn gc_resource<H: 'static>(&mut self, handle: H, close: fn(H)) -> Gc<GcResource<H>> {
+        if let Entry::Vacant(entry) = self.finalizers.entry(TypeId::of::<GcResource<H>>()) {
+            entry.insert(Rc::new(|ptr| unsafe {
+                // SAFETY: Only called on boxes whose type_id matched.
+                ptr.cast::<GcBox<GcResource<H>>>().as_mut().value.close();
+            }));
+        }
+        self.gc(GcResource {
+            handle: Some(handle),
+            close,
+        })
+    }
+
+    pub fn gc_str(&mut self, s: &str) -> Gc<str> {
+        self.need_room(1, slice_box_layout::<u8>(s.len()).0.size());
+        let gc = Gc::new_str(s);
+        self.thread(&gc);
+        gc
+    }
+
+    // Equal strings share one object for as long as it lives. The table doesn't keep them alive.
+    pub fn intern(&mut self, s: &str) -> Gc<str> {
+        if let Some(gc) = self.interned.get(s).and_then(Weak::upgrade) {
+            return gc;
+        }
+        let gc = self.gc_str(s);
+        self.interned.insert(s.into(), Gc::downgrade(&gc));
+        gc
+    }
+
+    // Like intern, but for small values of any type, by equality: equal values share one object
+    // while it lives. Each type's cache holds at most set_value_intern_limit values, evicting the
+    // least recently interned; an evicted value stays alive as long as it's reachable, but isn't
+    // shared any more.
+    pub fn intern_value<T: Trace + Eq + Hash + Clone + 'static>(&mut self, value: T) -> Gc<T> {
+        let limit = self.value_intern_limit;
+        let interner = self.value_interners.entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(ValueInterner::<T> { entries: HashMap::new(), clock: 0 }))
+            .downcast_mut::<ValueInterner<T>>()
+            .unwrap();
+        interner.clock += 1;
+        let now = interner.clock;
+        if let Some((weak, used)) = interner.entries.get_mut(&value) {
+            if let Some(gc) = weak.upgrade() {
+                *used = now;
+                return gc;
+            }
+        }
+        interner.entries.retain(|_, (weak, _)| weak.upgrade().is_some());
+        while interner.entries.len() >= limit.max(1) {
+            let oldest = interner.entries.iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(key, _)| key.clone())
+                .unwrap();
+            interner.entries.remove(&oldest);
+        }
+        let key = value.clone();
+        let gc = self.gc(value);
+        let interner = self.value_interners.get_mut(&TypeId::of::<T>()).unwrap()
+            .downcast_mut::<ValueInterner<T>>()
+            .unwrap();
+        interner.entries.insert(key, (Gc::downgrade(&gc), now));
+        gc
+    }
+
+    pub fn set_value_intern_limit(&mut self, limit: usize) {
+        self.value_intern_limit = limit;
+    }
+
+    pub fn gc_with_id<T: Trace + 'static>(&mut self, value: T) -> (Gc<T>, ObjId) {
+        let gc = self.gc(value);
+        let inner = unsafe {
+            // SAFETY: Freshly allocated, so certainly live.
+            (*gc.ptr.get()).inner.unwrap()
+        };
+        let id = self.ids.assign(erase(inner));
+        (gc, id)
+    }
+
+    pub fn get_by_id(&self, id: ObjId) -> Option<GcPtrNonNull> {
+        self.ids.slots.get(id.index as usize)
+            .filter(|slot| slot.tag == id.tag)
+            .and_then(|slot| slot.ptr)
+    }
+
+    // A slice that's never collected, e.g. for lookup tables. Unlike a root, it can't be unrooted.
+    pub fn gc_pinned_slice<T: Trace + 'static>(&mut self, items: impl IntoIterator<Item = T>) -> Gc<[T]> {
+        let gc = self.gc_slice(items);
+        self.permanent.push(erase(unsafe {
+            // SAFETY: Freshly allocated, so certainly live.
+            (*gc.ptr.get()).inner.unwrap()
+        }));
+        gc
+    }
+
+    // on_collect runs exactly once, when the sweep reclaims the object--not when handles drop.
+    pub fn gc_with_drop_guard<T: Trace + 'static, F: FnOnce() + 'static>(
+        &mut self, value: T, on_collect: F,
+    ) -> Gc<T> {
+        let gc = self.gc(value);
+        self.on_finalize(&gc, Box::new(on_collect));
+        gc
+    }
+
+    // Adds f to what the sweep runs when it reclaims the object (after anything added before),
+    // as with gc_with_drop_guard. Returns false, dropping f, if it's already been collected, or
+    // isn't on this arena's list.
+    pub fn on_finalize<T: ?Sized>(&mut self, gc: &Gc<T>, f: Box<dyn FnOnce()>) -> bool {
+        let inner = match unsafe { (*gc.ptr.get()).inner } {
+            Some(inner) => inner,
+            None => return false,
+        };
+        // SAFETY: Live.
+        let gcbox = unsafe { inner.as_ref() };
+        if gcbox.arena_id != self.id {
+            return false;
+        }
+        self.drop_guards.entry(gcbox.generation).or_default().push(f);
+        true
+    }
+
+    pub fn gc_cow<T: Trace + Clone + 'static>(&mut self, value: T) -> GcCow<T> {
+        GcCow {
+            gc: self.gc(value),
+        }
+    }
+
+    pub fn gc_recursive_builder<T, F>(&mut self, make: F) -> TreeBuilder<'_, T, F>
+        where T: Trace + 'static, F: FnMut(Vec<Gc<T>>) -> T
+    {
+        TreeBuilder {
+            arena: self,
+            make,
+            open: vec![Vec::new()],
+            temps: Vec::new(),
+        }
+    }
+
+    pub fn gc_shared<T: Trace + 'static>(&mut self, value: T) -> SharedGc<T> {
+        let gc = self.gc(value);
+        let id = self.shared_root(&gc);
+        SharedGc {
+            slot: Rc::new(SharedSlot {
+                id,
+                table: Rc::clone(&self.shared),
+                gc: RefCell::new(gc),
+            }),
+        }
+    }
+
+    // Roots the new object for as long as the guard lives.
+    pub fn root_guard<T: Trace + 'static>(&mut self, value: T) -> (Gc<T>, RootGuard) {
+        let gc = self.gc(value);
+        let guard = RootGuard {
+            id: self.shared_root(&gc),
+            table: Rc::clone(&self.shared),
+        };
+        (gc, guard)
+    }
+
+    // gc must be freshly allocated.
+    fn shared_root<T: ?Sized>(&mut self, gc: &Gc<T>) -> u64 {
+        let mut table = self.shared.borrow_mut();
+        let id = table.next_id;
+        table.next_id += 1;
+        table.roots.insert(id, erase(unsafe { (*gc.ptr.get()).inner.unwrap() }));
+        id
+    }
+
+    pub fn root<T: Trace + 'static>(&mut self, value: T) -> Gc<T> {
+        let gc = self.gc(value);
+        if self.deferred_rooting {
+            unsafe {
+                // SAFETY: Freshly allocated, so certainly live.
+                (*gc.ptr.get()).inner.unwrap().as_mut().root_pending = true;
+            }
+            self.pending_roots += 1;
+            return gc;
+        }
+        unsafe {
+            // SAFETY: Most of the worry here is just dereferencing the UnsafeCell. Since the
+            // interior type is Copy, this should be fine.
+            self.push_root(erase((*gc.ptr.get()).inner.unwrap()));
+        }
+        gc
+    }
+
+    pub fn make_root<T: ?Sized + 'static>(&mut self, gc: &Gc<T>) {
+        let inner = unsafe {
+            // SAFETY: as above, this is mostly because we're using an UnsafeCell. No ref is coined
+            // here, so we're not making any promises about lifetimes we can't keep.
+            (*gc.ptr.get()).inner
+        };
+        if let Some(inner) = inner {  // NB the shadow
+            unsafe {
+                // SAFETY: Live, so its header can be read.
+                if inner.as_ref().root_index.is_none() {  // Avoid duplicates in the roots
+                    self.push_root(erase(inner));
+                }
+            }
+        }
+    }
+
+    pub fn unroot<T: ?Sized>(&mut self, gc: &Gc<T>) {
+        if let Some(mut inner) = unsafe { *gc.ptr.get() }.inner {
+            let gcbox = unsafe { inner.as_mut() };
+            if gcbox.root_pending {
+                gcbox.root_pending = false;
+                self.pending_roots -= 1;
+            }
+            unsafe { self.remove_root(erase(inner)) };
+        }
+    }
+
+    pub fn unroot_pinned<T: ?Sized>(&mut self, gc: &Pin<Gc<T>>) {
+        self.unroot(Gc::unpinned(gc));
+    }
+
+    // The box must be live and not in the roots already.
+    unsafe fn push_root(&mut self, pt: GcPtrNonNull) {
+        self.changes += 1;
+        pt.cast::<GcBox<()>>().as_mut().root_index = Some(self.roots.len());
+        self.roots.push(pt);
+    }
+
+    // The box must be live. Moves the last root into its place, if it was in the roots at all.
+    // Another arena's root_index isn't ours to take.
+    unsafe fn remove_root(&mut self, pt: GcPtrNonNull) {
+        let gcbox = pt.cast::<GcBox<()>>().as_mut();
+        if gcbox.arena_id != self.id {
+            return;
+        }
+        if let Some(index) = gcbox.root_index.take() {
+            self.roots.swap_remove(index);
+            if let Some(moved) = self.roots.get(index) {
+                moved.cast::<GcBox<()>>().as_mut().root_index = Some(index);
+            }
+        }
+    }
+
+    // After the roots are rearranged wholesale.
+    fn reindex_roots(&mut self) {
+        for (index, root) in self.roots.iter().enumerate() {
+            unsafe {
+                // SAFETY: Roots are live.
+                root.cast::<GcBox<()>>().as_mut().root_index = Some(index);
+            }
+        }
+    }
+
+    // Takes the object off the list and out of the roots, so the arena forgets it: it's never
+    // collected (or freed with the arena) and so leaks unless adopted again. Shared roots go too;
+    // any RootGuard or SharedGc for it just stops rooting it. Its drop guards and id stay here,
+    // for if this arena adopts it back. It's left marked, so marks reaching it stop there--what it
+    // refers to is no longer kept alive by it. Returns false if it was already collected. It must
+    // belong to this arena.
+    pub fn detach<T: ?Sized + 'static>(&mut self, gc: &Gc<T>) -> bool {
+        let inner = match unsafe { (*gc.ptr.get()).inner } {
+            Some(inner) => erase(inner),
+            None => return false,
+        };
+        let other = |p: &GcPtrNonNull| !ptr::eq(p.as_ptr() as *const (), inner.as_ptr() as *const ());
+        unsafe { self.remove_root(inner) };
+        self.pinned.retain(other);
+        self.permanent.retain(other);
+        self.shared.borrow_mut().roots.retain(|_, p| other(p));
+        unsafe {
+            // SAFETY: Live, and on our list.
+            unlink(&mut self.start, inner);
+            let gcbox = inner.cast::<GcBox<()>>().as_mut();
+            if gcbox.root_pending {
+                gcbox.root_pending = false;
+                self.pending_roots -= 1;
+            }
+            self.uncount(gcbox.type_id, gcbox.size);
+            gcbox.arena_id = 0;
+            gcbox.prev = null_gcptr();
+            gcbox.next = null_gcptr();
+            gcbox.mark = true;
+        }
+        true
+    }
+
+    // Frees a box after moving its value out, as for Gc::try_unwrap. The box must be live, and
+    // no Gc but the caller's can point at it.
+    unsafe fn free_unwrapped(&mut self, inner: GcPtrNonNull) {
+        let gc_box = inner.cast::<GcBox<()>>();
+        // detach does most of the forgetting, but the box is going away, so nothing may be left
+        // pointing at it at all.
+        self.worklist.retain(|&p| p != gc_box);
+        // Never collected, so its drop guards don't run.
+        self.drop_guards.remove(&gc_box.as_ref().generation);
+        self.ids.release(gc_box.as_ref().generation);
+        if let Some(dead) = (gc_box.as_ref().free)(gc_box, Free::KeepValue) {
+            self.dead_allocs.push(dead);
+        }
+    }
+
+    // Puts a detached object back on the list, unrooted, to be collected like any other. Returns
+    // false if it was already collected, or isn't detached.
+    pub fn adopt<T: ?Sized + 'static>(&mut self, gc: &Gc<T>) -> bool {
+        let inner = match unsafe { (*gc.ptr.get()).inner } {
+            Some(inner) => erase(inner),
+            None => return false,
+        };
+        unsafe {
+            // SAFETY: Live. Only detached boxes have no arena id, and they're on no list.
+            let gcbox = inner.cast::<GcBox<()>>().as_mut();
+            if gcbox.arena_id != 0 {
+                return false;
+            }
+            gcbox.mark = false;
+            gcbox.arena_id = self.id;
+            self.count(gcbox.type_id, gcbox.size);
+            self.thread_box(inner);
+        }
+        true
+    }
+
+    // Moves an object from source into this arena, rooted here if it was rooted there. Only the
+    // object moves: what it refers to stays in source, to be collected there once nothing there
+    // refers to it, so move everything it needs along with it. Returns false if it was already
+    // collected, or isn't source's, or is rooted by a RootGuard or SharedGc of source's (which
+    // can't come along).
+    pub fn adopt_from<T: ?Sized + 'static>(&mut self, source: &mut Arena, gc: &Gc<T>) -> bool {
+        let inner = match unsafe { (*gc.ptr.get()).inner } {
+            Some(inner) => inner.cast::<GcBox<()>>(),
+            None => return false,
+        };
+        let rooted = unsafe {
+            // SAFETY: Live.
+            let gcbox = inner.as_ref();
+            if gcbox.arena_id != source.id {
+                return false;
+            }
+            gcbox.root_index.is_some() || gcbox.root_pending
+        };
+        let erased = erase(inner);
+        if source.shared.borrow().roots.values().any(|p| ptr::eq(p.as_ptr(), erased.as_ptr())) {
+            return false;
+        }
+        source.detach(gc);
+        // Ids index source's table, so they can't come along; drop guards do.
+        let generation = unsafe { inner.as_ref() }.generation;
+        source.ids.release(generation);
+        if let Some(guards) = source.drop_guards.remove(&generation) {
+            self.drop_guards.insert(generation, guards);
+        }
+        self.adopt(gc);
+        if rooted {
+            self.make_root(gc);
+        }
+        true
+    }
+
+    pub fn len(&self) -> usize {
+        self.live_objects
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start.is_null()
+    }
+
+    // Folds f over the values of every object of type T still on the list, live or garbage.
+    pub fn fold_type<T: 'static, B>(&self, init: B, mut f: impl FnMut(B, &T) -> B) -> B {
+        self.walk()
+            .map(|t| t.cast::<GcBox<()>>())
+            .filter(|gcbox| unsafe { gcbox.as_ref() }.type_id == TypeId::of::<T>())
+            .fold(init, |acc, gcbox| unsafe {
+                // SAFETY: List members are all live, and the type tag says this one's a T.
+                f(acc, &gcbox.cast::<GcBox<T>>().as_ref().value)
+            })
+    }
+
+    // What Gc::arena_id reports for objects in this arena.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    // Live objects of type T, without walking the list.
+    pub fn count_of_type<T: ?Sized + 'static>(&self) -> usize {
+        self.type_counts.get(&TypeId::of::<T>()).copied().unwrap_or(0)
+    }
+
+    pub fn roots_len(&self) -> usize {
+        self.roots.len() + self.pending_roots
+    }
+
+    // What make_root and root have rooted, including any with deferred rooting that the next
+    // mark will register--not pinned, permanent, or shared roots.
+    pub fn roots(&self) -> impl Iterator<Item = GcPtrNonNull> + '_ {
+        let pending = (self.pending_roots > 0).then(|| self.walk().filter(|t| unsafe {
+            // SAFETY: List members are all live.
+            t.cast::<GcBox<()>>().as_ref().root_pending
+        }));
+        self.roots.iter().copied().chain(pending.into_iter().flatten())
+    }
+
+    // Makes root cheaper, for rooting many objects at a time, by putting off registering the
+    // roots until the next collection--at the cost of a walk of the whole list then.
+    pub fn set_deferred_rooting(&mut self, enabled: bool) {
+        self.deferred_rooting = enabled;
+    }
+
+    fn register_deferred_roots(&mut self) {
+        if self.pending_roots == 0 {
+            return;
+        }
+        let pending: Vec<_> = self.walk().filter(|t| unsafe {
+            // SAFETY: List members are all live.
+            mem::take(&mut t.cast::<GcBox<()>>().as_mut().root_pending)
+        }).collect();
+        for t in pending {
+            unsafe {
+                // SAFETY: Pending roots weren't in the roots yet.
+                self.push_root(t);
+            }
+        }
+        self.pending_roots = 0;
+    }
+
+    // Presizes the mark worklist for heaps expected to need n pending boxes at once.
+    pub fn set_mark_capacity(&mut self, n: usize) {
+        self.worklist.reserve(n);
+    }
+
+    // Sorts the roots by address, so the start of a mark walks memory in order. Purely a
+    // reorganization; nothing observable changes.
+    pub fn optimize_root_order(&mut self) {
+        self.roots.sort_by_key(|ptr| ptr.as_ptr() as *const () as usize);
+        self.reindex_roots();
+    }
+
+    pub fn roots_snapshot(&self) -> RootsSnapshot {
+        RootsSnapshot {
+            roots: self.roots.iter().map(|&ptr| GcHandle {
+                ptr,
+                generation: unsafe { ptr.as_ref().generation() },
+            }).collect(),
+        }
+    }
+
+    // Replaces the root set with the snapshot's, minus any objects that have died since. Each root
+    // is resolved against the whole list, so this costs O(roots * heap).
+    pub fn restore_roots(&mut self, snap: RootsSnapshot) {
+        for root in mem::take(&mut self.roots) {
+            unsafe {
+                // SAFETY: Roots are live.
+                root.cast::<GcBox<()>>().as_mut().root_index = None;
+            }
+        }
+        self.changes += 1;
+        self.roots = snap.roots.iter().filter_map(|handle| handle.resolve(self)).collect();
+        self.reindex_roots();
+    }
+
+    // Keep these alive across collections until unpin_temporarily, without rooting them.
+    pub fn pin_temporarily(&mut self, ptrs: &[GcPtrNonNull]) {
+        self.changes += 1;
+        self.pinned.extend_from_slice(ptrs);
+    }
+
+    pub fn unpin_temporarily(&mut self) {
+        self.pinned.clear();
+    }
+
+    // None turns automatic collection off, as it is to start.
+    pub fn set_auto_collect_threshold(&mut self, n: Option<usize>) {
+        self.auto_collect = n;
+    }
+
+    pub fn set_auto_collect_bytes(&mut self, bytes: Option<usize>) {
+        self.auto_collect_bytes = bytes;
+    }
+
+    // Hard caps on the live heap, for running untrusted code. Only gc and gc_try check them.
+    pub fn set_max_objects(&mut self, n: Option<usize>) {
+        self.max_objects = n;
+    }
+
+    pub fn set_max_bytes(&mut self, bytes: Option<usize>) {
+        self.max_bytes = bytes;
+    }
+
+    // None if there's no such threshold set.
+    pub fn allocs_until_collection(&self) -> Option<usize> {
+        self.auto_collect.map(|n| n.saturating_sub(self.allocs_since_collection))
+    }
+
+    pub fn bytes_until_collection(&self) -> Option<usize> {
+        self.auto_collect_bytes.map(|n| n.saturating_sub(self.bytes_since_collection))
+    }
+
+    // Called at every mark, after the roots, to visit whatever else should survive this time
+    // around. It mustn't allocate in or collect the arena.
+    pub fn set_root_scanner(&mut self, f: impl FnMut(&Visitor) + 'static) {
+        self.root_scanner = Some(Box::new(f));
+    }
+
+    // Whether collections time their mark and sweep phases, for Collection::timing.
+    pub fn set_timing(&mut self, enabled: bool) {
+        self.timing = enabled;
+    }
+
+    // Runs f right after each allocation, with the new box and its type; e.g. to capture a
+    // backtrace for allocation-site attribution. f mustn't allocate in or collect the arena.
+    pub fn set_alloc_hook(&mut self, f: impl Fn(GcPtrNonNull, TypeId) + 'static) {
+        self.alloc_hook = Some(Box::new(f));
+    }
+
+    // Calls yield_fn every chunk objects (live or not) the sweep goes over, e.g. to pump an event
+    // loop during a long one. It mustn't touch the arena.
+    pub fn set_sweep_yield(&mut self, chunk: usize, yield_fn: impl Fn() + 'static) {
+        assert!(chunk > 0, "sweep yield chunk must be positive");
+        self.sweep_yield = Some((chunk, Box::new(yield_fn)));
+    }
+
+    // With drops deferred, the sweep (or anything else reclaiming objects) runs finalizers and
+    // marks objects collected as usual, but leaves dropping their values and freeing their boxes
+    // to run_deferred_drops, for the host to call when it's convenient. Dropping the arena runs
+    // them too.
+    pub fn set_defer_drops(&mut self, enabled: bool) {
+        self.defer_drops = enabled;
+    }
+
+    // Returns how many boxes were freed.
+    pub fn run_deferred_drops(&mut self) -> usize {
+        let boxes = mem::take(&mut self.deferred_drops);
+        for &boxptr in &boxes {
+            unsafe {
+                // SAFETY: Reclaimed, but not freed; the alloc's already been detached, so this
+                // gives nothing back.
+                (boxptr.as_ref().free)(boxptr, Free::All);
+            }
+        }
+        boxes.len()
+    }
+
+    pub fn set_metrics_sink(&mut self, sink: impl MetricsSink + 'static) {
+        self.metrics = Some(Box::new(sink));
+    }
+
+    // Runs f on each object the sweep is about to free, before any finalizer or drop. There's only
+    // one; setting another replaces it.
+    pub fn on_unreachable(&mut self, f: impl FnMut(GcPtrNonNull) + 'static) {
+        self.on_unreachable = Some(Box::new(f));
+    }
+
+    // Runs f on every object of type T just before the sweep frees it. One per type; registering
+    // again replaces the old one.
+    pub fn register_finalizer<T: 'static>(&mut self, f: impl Fn(&mut T) + 'static) {
+        self.finalizers.insert(TypeId::of::<T>(), Rc::new(move |ptr| {
+            unsafe {
+                // SAFETY: Only called on boxes whose type_id matched T's.
+                let gcbox = ptr.cast::<GcBox<T>>().as_mut();
+                // f could move a pinned value out.
+                if gcbox.alloc.as_ref().is_some_and(|alloc| (*alloc.get()).pinned) {
+                    return;
+                }
+                f(&mut gcbox.value)
+            }
+        }));
+    }
+
+    // Maps how many collections an object has survived to how many live objects have that age.
+    pub fn age_histogram(&self) -> BTreeMap<u32, usize> {
+        let mut histogram = BTreeMap::new();
+        for t in self.walk() {
+            let age = unsafe { t.cast::<GcBox<()>>().as_ref().survived_collections };
+            *histogram.entry(age).or_insert(0) += 1;
+        }
+        histogram
+    }
+
+    // Every object, oldest epoch first (then by allocation order within one), for diffing
+    // against a later snapshot.
+    pub fn stable_walk(&self) -> Vec<StableObjectRef> {
+        let mut objects: Vec<_> = self.walk().map(|t| {
+            let gcbox = unsafe { t.cast::<GcBox<()>>().as_ref() };
+            StableObjectRef {
+                id: gcbox.generation,
+                type_id: gcbox.type_id,
+                epoch: gcbox.epoch,
+            }
+        }).collect();
+        objects.sort_by_key(|o| (o.epoch, o.id));
+        objects
+    }
+
+    pub fn snapshot(&mut self) -> HeapSnapshot {
+        HeapSnapshot {
+            reachable: self.reachable().map(|(o, _)| o.id).collect(),
+        }
+    }
+
+    pub fn reachability_diff(&mut self, before: &HeapSnapshot) -> ReachDiff {
+        let mut new: Vec<_> = self.reachable()
+            .filter(|(o, survived)| *survived > 0 && !before.reachable.contains(&o.id))
+            .map(|(o, _)| o)
+            .collect();
+        new.sort_by_key(|o| (o.epoch, o.id));
+        ReachDiff { new }
+    }
+
+    // Marks from the roots and reads the marks back off, leaving everything unmarked again.
+    fn reachable(&mut self) -> impl Iterator<Item = (StableObjectRef, u32)> {
+        self.unmark_all();
+        self.mark_from_roots(ptr::null());
+        let reachable: Vec<_> = self.walk().filter_map(|t| {
+            let gcbox = unsafe { t.cast::<GcBox<()>>().as_ref() };
+            gcbox.mark.then_some((StableObjectRef {
+                id: gcbox.generation,
+                type_id: gcbox.type_id,
+                epoch: gcbox.epoch,
+            }, gcbox.survived_collections))
+        }).collect();
+        self.unmark_all();
+        reachable.into_iter()
+    }
+
+    // The n biggest live boxes, biggest first.
+    pub fn largest_live(&self, n: usize) -> Vec<(GcPtrNonNull, usize)> {
+        let mut sizes = self.walk()
+            .map(|t| (t, unsafe { t.cast::<GcBox<()>>().as_ref().size }))
+            .collect::<Vec<_>>();
+        sizes.sort_by_key(|&(_, size)| std::cmp::Reverse(size));
+        sizes.truncate(n);
+        sizes
+    }
+
+    pub fn iter<'s>(&'s mut self) -> ArenaIter<'s> {
+        ArenaIter {
+            cur: self.start,
+            marker: PhantomData,
+        }
+    }
+
+    // Walk for internal use that doesn't tie up all of self; callers are responsible for not
+    // handing out aliasing access to the boxes.
+    fn walk(&self) -> ArenaIter<'_> {
+        ArenaIter {
+            cur: self.start,
+            marker: PhantomData,
+        }
+    }
+
+    fn find_live(&self, addr: usize, generation: u64) -> Option<GcPtrNonNull> {
+        // Only compare against boxes we know are live, so a stale pointer never gets dereferenced.
+        self.walk().find(|t| {
+            t.as_ptr() as *const () as usize == addr
+                && unsafe { t.as_ref().generation() } == generation
+        })
+    }
+
+    // This walks the whole list, as find_live has to in order to trust nothing about the key, so
+    // it costs O(heap) per call. Keys are cheap to hash and compare, not to resolve: where that's
+    // too slow, keep a Weak alongside the key instead.
+    pub fn resolve(&self, key: WeakKey) -> Option<GcPtrNonNull> {
+        self.find_live(key.addr, key.generation)
+    }
+
+    // Returns None if ptr isn't a box currently in this arena. Like resolve, this walks the list.
+    pub fn handle(&self, ptr: GcPtrNonNull) -> Option<GcHandle> {
+        self.walk()
+            .find(|t| ptr::eq(t.as_ptr() as *const (), ptr.as_ptr() as *const ()))
+            .map(|t| GcHandle {
+                ptr: t,
+                generation: unsafe { t.as_ref().generation() },
+            })
+    }
+
+    pub fn collect(&mut self) -> Collection {
+        self.collect_for(CollectReason::Manual)
+    }
+
+    fn collect_for(&mut self, reason: CollectReason) -> Collection {
+        self.mark();
+        self.sweep_for(reason)
+    }
+
+    // Frees everything, rooted or not, as dropping the arena would, but leaves the arena to be
+    // used again. Surviving handles see their objects collected. Does nothing from inside a
+    // collection.
+    pub fn clear(&mut self) {
+        if self.collecting {
+            return;
+        }
+        self.collecting = true;
+        self.unmark_all();
+        self.roots.clear();
+        self.pinned.clear();
+        self.permanent.clear();
+        self.pending_roots = 0;
+        self.shared.borrow_mut().roots.clear();
+        while let Some(t) = NonNull::new(self.start as *mut dyn Traverse) {
+            unsafe {
+                unlink(&mut self.start, t);
+                self.reclaim(t);
+            }
+        }
+        self.collecting = false;
+    }
+
+    // collect, also returning the address of every box it freed, for matching up against handles
+    // (or GcPtrs) expected to die.
+    pub fn collect_debug(&mut self) -> (Collection, Vec<*const ()>) {
+        if self.collecting {
+            // Leave the log to the collection underway.
+            return (self.collect(), Vec::new());
+        }
+        self.freed_log = Some(Vec::new());
+        let col = self.collect();
+        (col, self.freed_log.take().unwrap_or_default())
+    }
+
+    // Why the last completed collection ran; None if there hasn't been one.
+    pub fn last_collect_reason(&self) -> Option<CollectReason> {
+        self.last_collect_reason
+    }
+
+    // Checks cancel as it goes, up until the sweep starts freeing. If it's set by then, everything
+    // is unmarked again and nothing is freed; after that, the collection runs to the end.
+    pub fn collect_cancellable(&mut self, cancel: &AtomicBool) -> CollectResult {
+        if self.collecting {
+            return CollectResult::Completed(Collection::reentrant(CollectReason::Manual));
+        }
+        self.mark_phase(cancel);
+        if cancel.load(Ordering::Relaxed) {
+            self.unmark_all();
+            return CollectResult::Cancelled;
+        }
+        self.sweep_phase(Some(cancel), CollectReason::Manual)
+    }
+
+    // The two halves of collect, for collectors of one's own. mark marks everything reachable
+    // from the roots (clearing any old marks first), returning how many that is; sweep frees
+    // everything unmarked and clears the marks on the rest. If anything's been allocated or
+    // rooted since the mark--or there wasn't one--sweep marks again first, so it never frees
+    // anything live. Either does nothing from inside a collection.
+    pub fn mark(&mut self) -> usize {
+        if self.collecting {
+            return 0;
+        }
+        self.mark_phase(ptr::null());
+        self.walk().filter(|t| unsafe { t.as_ref().marked() }).count()
+    }
+
+    pub fn sweep(&mut self) -> Collection {
+        self.sweep_for(CollectReason::Manual)
+    }
+
+    fn sweep_for(&mut self, reason: CollectReason) -> Collection {
+        match self.sweep_phase(None, reason) {
+            CollectResult::Completed(col) => col,
+            CollectResult::Cancelled => unreachable!("uncancellable collection was cancelled"),
+        }
+    }
+
+    // Guarded like a collection, since a root scanner could try to start one.
+    fn mark_phase(&mut self, cancel: *const AtomicBool) {
+        self.collecting = true;
+        let started = self.timing.then(Instant::now);
+        self.unmark_all();
+        self.shared.borrow_mut().repointed = false;
+        self.mark_from_roots(cancel);
+        self.marked_at = Some(self.changes);
+        self.mark_started = started;
+        self.collecting = false;
+    }
+
+    // A collection from inside another does nothing, and reports collecting nothing out of
+    // nothing.
+    fn sweep_phase(&mut self, cancel: Option<&AtomicBool>, reason: CollectReason) -> CollectResult {
+        if self.collecting {
+            return CollectResult::Completed(Collection::reentrant(reason));
+        }
+        if self.incremental {
+            self.collecting = true;
+            self.finish_incremental_mark();
+            self.collecting = false;
+        } else if self.marked_at != Some(self.changes) || self.shared.borrow().repointed {
+            self.mark_phase(ptr::null());
+        }
+        self.collecting = true;
+        let started = self.mark_started.take();
+        let result = self.sweep_inner(cancel, reason, started);
+        self.marked_at = None;
+        self.collecting = false;
+        result
+    }
+
+    // An incremental mark does the work of mark a step at a time, interleaved with the mutator:
+    // start_incremental_mark shades the roots, each mark_step traces up to budget objects, and
+    // sweep finishes it off. Objects allocated in the meantime survive. Any other collection, or
+    // anything else that marks, abandons it.
+    //
+    // Storing a reference into an object while the mark is underway must go through
+    // write_barrier, or the mark may miss what it refers to--leaving a dangling reference in the
+    // heap after the sweep.
+    pub fn start_incremental_mark(&mut self) {
+        self.register_deferred_roots();
+        self.unmark_all();
+        let visitor = Visitor::new(ptr::null(), mem::take(&mut self.worklist));
+        {
+            let shared = self.shared.borrow();
+            for &r in self.roots.iter().chain(&self.pinned).chain(&self.permanent).chain(shared.roots.values()) {
+                unsafe {
+                    // SAFETY: Roots are all live.
+                    visitor.visit_ptr(r);
+                }
+            }
+        }
+        if let Some(scanner) = &mut self.root_scanner {
+            scanner(&visitor);
+        }
+        self.worklist = visitor.worklist.into_inner();
+        self.incremental = true;
+    }
+
+    // Returns whether the mark has run out of work, when it's time to sweep.
+    pub fn mark_step(&mut self, budget: usize) -> bool {
+        if !self.incremental {
+            return true;
+        }
+        let visitor = Visitor::new(ptr::null(), mem::take(&mut self.worklist));
+        let done = visitor.drain_some(budget);
+        self.worklist = visitor.worklist.into_inner();
+        done
+    }
+
+    // Dijkstra's barrier: storing a reference to child into parent shades child, if parent's
+    // already been reached. Does nothing outside an incremental mark, or if either is collected.
+    pub fn write_barrier<T: ?Sized, U: ?Sized>(&mut self, parent: &Gc<T>, child: &Gc<U>) {
+        if !self.incremental {
+            return;
+        }
+        let (parent, child) = unsafe { ((*parent.ptr.get()).inner, (*child.ptr.get()).inner) };
+        if let (Some(parent), Some(child)) = (parent, child) {
+            let (parent, mut child) = (parent.cast::<GcBox<()>>(), child.cast::<GcBox<()>>());
+            unsafe {
+                // SAFETY: Both live.
+                if parent.as_ref().mark && !child.as_ref().mark {
+                    child.as_mut().mark = true;
+                    self.worklist.push(child);
+                }
+            }
+        }
+    }
+
+    // Rescans the roots, which may have changed since the start, and traces whatever's left.
+    fn finish_incremental_mark(&mut self) {
+        let visitor = Visitor::new(ptr::null(), mem::take(&mut self.worklist));
+        visitor.drain();
+        self.mark_roots(&visitor);
+        self.scan_roots(&visitor);
+        self.worklist = visitor.into_worklist();
+        self.incremental = false;
+    }
+
+    // started is when the mark began, for timing.
+    fn sweep_inner(
+        &mut self, cancel: Option<&AtomicBool>, reason: CollectReason, started: Option<Instant>,
+    ) -> CollectResult {
+        let cancelled = || cancel.is_some_and(|c| c.load(Ordering::Relaxed));
+        let mut col = Collection {
+            total: 0, collected: 0, bytes_collected: 0, bytes_live: 0, timing: None, reason,
+        };
+        let cycles = if self.count_cycles { self.garbage_cycles() } else { 0 };
+        // Past here things get freed, and a collection stopped partway would have freed some
+        // garbage without any of the bookkeeping that goes with it.
+        if cancelled() {
+            self.unmark_all();
+            return CollectResult::Cancelled;
+        }
+        let marked = started.map(|_| Instant::now());
+        let mut start = self.start;
+        let mut freed = FreedSet::default();
+        // Not self.walk(), which would read each box before we could check it.
+        let mut cur = self.start;
+        while let Some(t) = NonNull::new(cur as *mut dyn Traverse) {
+            freed.check(t);
+            cur = unsafe { t.as_ref().next() };
+            // Roots are never freed, marked or not: nothing would take them out of the roots.
+            let keep = unsafe {
+                let gcbox = t.cast::<GcBox<()>>().as_ref();
+                gcbox.mark || gcbox.root_index.is_some() || gcbox.root_pending
+            };
+            if keep {
+                unsafe {
+                    let gcbox = t.cast::<GcBox<()>>().as_mut();
+                    gcbox.mark = false;
+                    gcbox.survived_collections = gcbox.survived_collections.saturating_add(1);
+                    col.bytes_live += gcbox.size;
+                }
+            } else {
+                if let Some(log) = &mut self.freed_log {
+                    log.push(t.as_ptr() as *const ());
+                }
+                unsafe {
+                    col.bytes_collected += t.cast::<GcBox<()>>().as_ref().size;
+                    unlink(&mut start, t);
+                    self.reclaim(t);
+                }
+                freed.insert(t);
+                col.collected += 1;
+            }
+            col.total += 1;
+            if let Some((chunk, yield_fn)) = &self.sweep_yield {
+                if col.total.is_multiple_of(*chunk) {
+                    yield_fn();
+                }
+            }
+        }
+        self.start = start;
+        self.cycles_reclaimed += cycles;
+        self.collections += 1;
+        self.last_collect_reason = Some(reason);
+        self.allocs_since_collection = 0;
+        self.bytes_since_collection = 0;
+        // Once no Weaks are left, nothing can observe the alloc; ours may be the last reference.
+        self.dead_allocs.retain(|alloc| {
+            let keep = Rc::weak_count(alloc) > 0;
+            if !keep {
+                release_dead_alloc(alloc);
+            }
+            keep
+        });
+        self.interned.retain(|_, weak| weak.upgrade().is_some());
+        col.timing = started.zip(marked).map(|(started, marked)| CollectTiming {
+            mark: marked - started,
+            sweep: marked.elapsed(),
+        });
+        if let Some(sink) = &mut self.metrics {
+            sink.on_collection(&col);
+        }
+        CollectResult::Completed(col)
+    }
+
+    // Returns how many boxes there are, since it's walking them anyway. This abandons any
+    // incremental mark in progress.
+    fn unmark_all(&mut self) -> usize {
+        self.incremental = false;
+        self.marked_at = None;
+        self.mark_started = None;
+        self.worklist.clear();
+        let mut total = 0;
+        for mut t in self.iter() {
+            unsafe {
+                // SAFETY: We expect these to have been already constructed and aligned normally,
+                // and this iterator--strictly speaking--returns only non-null pointers.
+                t.as_mut().unmark();
+            }
+            total += 1;
+        }
+        total
+    }
+
+    // For leak checks in tests: marks from the roots and panics, listing the unreachable objects
+    // by type, if anything is garbage. Nothing is freed either way.
+    pub fn assert_no_garbage(&mut self) {
+        self.unmark_all();
+        self.mark_from_roots(ptr::null());
+        let mut garbage: BTreeMap<&'static str, (TypeId, usize)> = BTreeMap::new();
+        for t in self.walk() {
+            if !unsafe { t.as_ref().marked() } {
+                let gcbox = unsafe { t.cast::<GcBox<()>>().as_ref() };
+                garbage.entry(gcbox.type_name).or_insert((gcbox.type_id, 0)).1 += 1;
+            }
+        }
+        if !garbage.is_empty() {
+            let listing = garbage.iter()
+                .map(|(name, (id, count))| format!("{} x {} ({:?})", count, name, id))
+                .collect::<Vec<_>>();
+            panic!("arena has unreachable objects: {}", listing.join(", "));
+        }
+    }
+
+    // How many Weaks still point at objects this arena has collected. Dropped Weaks stop counting
+    // right away; their allocs are released at the next collection.
+    pub fn dead_weak_count(&self) -> usize {
+        self.dead_allocs.iter().map(Rc::weak_count).sum()
+    }
+
+    // Over the arena's lifetime, how many of the garbage groups collected formed a cycle, counting
+    // only while set_count_cycles is on.
+    pub fn cycles_reclaimed(&self) -> u64 {
+        self.cycles_reclaimed
+    }
+
+    // Counting cycles means tracing all the garbage again on every sweep, so it's off by default.
+    pub fn set_count_cycles(&mut self, enabled: bool) {
+        self.count_cycles = enabled;
+    }
+
+    // Counts the cycles among the unmarked boxes after a mark, as strongly connected components
+    // with at least one internal edge. The edges come from tracing each box with a recording
+    // visitor.
+    fn garbage_cycles(&mut self) -> u64 {
+        let garbage: Vec<_> = self.walk()
+            .filter(|t| !unsafe { t.as_ref().marked() })
+            .map(|t| t.cast::<GcBox<()>>())
+            .collect();
+        if garbage.is_empty() {
+            return 0;
+        }
+        let index: HashMap<_, _> = garbage.iter().enumerate().map(|(i, b)| (b.as_ptr(), i)).collect();
+        let recorder = Visitor::recording(mem::take(&mut self.worklist));
+        let edges: Vec<Vec<usize>> = garbage.iter().map(|&gcbox| {
+            unsafe {
+                // SAFETY: Unmarked boxes haven't been swept yet.
+                (gcbox.as_ref().trace)(gcbox, &recorder);
+            }
+            // Edges out to live objects can't be part of a garbage cycle.
+            recorder.worklist.borrow_mut().drain(..)
+                .filter_map(|child| index.get(&child.as_ptr()).copied())
+                .collect()
+        }).collect();
+        self.worklist = recorder.into_worklist();
+        count_cycles(&edges)
+    }
+
+    pub fn to_graph(&mut self) -> ObjectGraph {
+        self.register_deferred_roots();
+        let boxes: Vec<_> = self.walk().map(|t| t.cast::<GcBox<()>>()).collect();
+        let index: HashMap<_, _> = boxes.iter().enumerate().map(|(i, b)| (b.as_ptr(), i)).collect();
+        let mut nodes: Vec<_> = boxes.iter().map(|&gcbox| {
+            let header = unsafe { gcbox.as_ref() };
+            NodeInfo {
+                ptr: erase(gcbox),
+                type_id: header.type_id,
+                type_name: header.type_name,
+                size: header.size,
+                root: false,
+            }
+        }).collect();
+        {
+            let shared = self.shared.borrow();
+            let roots = self.roots.iter().chain(&self.pinned).chain(&self.permanent).chain(shared.roots.values());
+            for root in roots {
+                if let Some(&i) = index.get(&(root.as_ptr() as *mut GcBox<()>)) {
+                    nodes[i].root = true;
+                }
+            }
+        }
+        let recorder = Visitor::recording(mem::take(&mut self.worklist));
+        let mut edges = Vec::new();
+        for (from, &gcbox) in boxes.iter().enumerate() {
+            unsafe {
+                // SAFETY: List members are all live.
+                (gcbox.as_ref().trace)(gcbox, &recorder);
+            }
+            edges.extend(recorder.worklist.borrow_mut().drain(..)
+                .filter_map(|child| index.get(&child.as_ptr()).map(|&to| (from, to))));
+        }
+        self.worklist = recorder.into_worklist();
+        ObjectGraph { nodes, edges }
+    }
+
+    // Moves everything unreachable into a new arena, to be finalized and freed whenever that's
+    // dropped. The finalizers registered here carry over.
+    pub fn split_off_unreachable(&mut self) -> Arena {
+        self.unmark_all();
+        self.mark_from_roots(ptr::null());
+        let garbage: Vec<_> = self.walk().filter(|t| !unsafe { t.as_ref().marked() }).collect();
+        let mut split = Arena::new();
+        split.finalizers = self.finalizers.clone();
+        for t in garbage {
+            unsafe {
+                unlink(&mut self.start, t);
+                let gcbox = t.cast::<GcBox<()>>().as_mut();
+                // Ids index this arena's table, so they can't come along; drop guards do.
+                self.ids.release(gcbox.generation);
+                if let Some(guards) = self.drop_guards.remove(&gcbox.generation) {
+                    split.drop_guards.insert(gcbox.generation, guards);
+                }
+                self.uncount(gcbox.type_id, gcbox.size);
+                split.count(gcbox.type_id, gcbox.size);
+                gcbox.arena_id = split.id;
+                split.thread_box(t);
+            }
+        }
+        split
+    }
+
+    // A shortest chain of references from some root to target, both ends included; for finding
+    // out why something isn't being collected. None if it's unreachable (or already collected).
+    pub fn path_to<T: ?Sized>(&mut self, target: &Gc<T>) -> Option<Vec<GcPtrNonNull>> {
+        let target = unsafe { (*target.ptr.get()).inner }?.cast::<GcBox<()>>();
+        self.register_deferred_roots();
+        // Each box reached, and the one it was first reached from (None for roots).
+        let mut parents: HashMap<*mut GcBox<()>, Option<NonNull<GcBox<()>>>> = HashMap::new();
+        let mut queue = VecDeque::new();
+        {
+            let shared = self.shared.borrow();
+            let roots = self.roots.iter().chain(&self.pinned).chain(&self.permanent).chain(shared.roots.values());
+            for r in roots.map(|r| r.cast::<GcBox<()>>()) {
+                if let Entry::Vacant(entry) = parents.entry(r.as_ptr()) {
+                    entry.insert(None);
+                    queue.push_back(r);
+                }
+            }
+        }
+        let recorder = Visitor::recording(mem::take(&mut self.worklist));
+        while let Some(gcbox) = queue.pop_front() {
+            if gcbox == target {
+                break;
+            }
+            unsafe {
+                // SAFETY: Everything reachable from the roots is live.
+                (gcbox.as_ref().trace)(gcbox, &recorder);
+            }
+            for child in recorder.worklist.borrow_mut().drain(..) {
+                if let Entry::Vacant(entry) = parents.entry(child.as_ptr()) {
+                    entry.insert(Some(gcbox));
+                    queue.push_back(child);
+                }
+            }
+        }
+        self.worklist = recorder.into_worklist();
+        let mut step = *parents.get(&target.as_ptr())?;
+        let mut path = vec![erase(target)];
+        while let Some(parent) = step {
+            path.push(erase(parent));
+            step = parents[&parent.as_ptr()];
+        }
+        path.reverse();
+        Some(path)
+    }
+
+    fn mark_from_roots(&mut self, cancel: *const AtomicBool) {
+        self.register_deferred_roots();
+        let visitor = Visitor::new(cancel, mem::take(&mut self.worklist));
+        self.mark_roots(&visitor);
+        self.scan_roots(&visitor);
+        self.worklist = visitor.into_worklist();
+    }
+
+    fn scan_roots(&mut self, visitor: &Visitor) {
+        if let Some(scanner) = &mut self.root_scanner {
+            if !visitor.cancelled() {
+                scanner(visitor);
+                visitor.drain();
+            }
+        }
+    }
+
+    // Marks everything reachable from any of the root sets, stopping early if the visitor's
+    // collection is cancelled.
+    fn mark_roots(&self, visitor: &Visitor) {
+        // Held only for the mark; the sweep may drop SharedGcs, which need it back.
+        let shared = self.shared.borrow();
+        // Strictly speaking, we don't mutate the _values_ in these lists, but we do mutate their
+        // referents through the underlying raw pointer.
+        for r in self.roots.iter().chain(&self.pinned).chain(&self.permanent).chain(shared.roots.values()).copied() {
+            unsafe {
+                // SAFETY: By virtue of this very line, the roots list cannot be left with dangling
+                // pointers (as all member objects are marked).
+                visitor.visit_ptr(r);
+            }
+            visitor.drain();
+            if visitor.cancelled() {
+                break;
+            }
+        }
+    }
+
+    fn count(&mut self, type_id: TypeId, size: usize) {
+        *self.type_counts.entry(type_id).or_insert(0) += 1;
+        self.live_objects += 1;
+        self.live_bytes += size;
+    }
+
+    fn uncount(&mut self, type_id: TypeId, size: usize) {
+        self.live_objects -= 1;
+        self.live_bytes -= size;
+        if let Entry::Occupied(mut count) = self.type_counts.entry(type_id) {
+            *count.get_mut() -= 1;
+            if *count.get() == 0 {
+                count.remove();
+            }
+        }
+    }
+
+    // Finalize and free a box that's already been unlinked.
+    unsafe fn reclaim(&mut self, t: GcPtrNonNull) {
+        let boxptr = t.cast::<GcBox<()>>();
+        if let Some(f) = &mut self.on_unreachable {
+            f(t);
+        }
+        if let Some(finalize) = self.finalizers.get(&boxptr.as_ref().type_id) {
+            finalize(boxptr);
+        }
+        // Most objects have neither, so skip hashing for them when nothing does.
+        let generation = boxptr.as_ref().generation;
+        if !self.drop_guards.is_empty() {
+            for guard in self.drop_guards.remove(&generation).into_iter().flatten() {
+                guard();
+            }
+        }
+        if !self.ids.indices.is_empty() {
+            self.ids.release(generation);
+        }
+        self.uncount(boxptr.as_ref().type_id, boxptr.as_ref().size);
+        if let Some(sink) = &mut self.metrics {
+            sink.on_free(boxptr.as_ref().type_id);
+        }
+        // Collect the box again and let it drop (as whatever it really is--the Traverse vtable on
+        // t doesn't know).
+        let dead = if self.defer_drops {
+            self.deferred_drops.push(boxptr);
+            (boxptr.as_ref().detach)(boxptr)
+        } else if let Some(layout) = boxptr.as_ref().layout.filter(|_| self.pool_len < POOL_LIMIT) {
+            let dead = (boxptr.as_ref().free)(boxptr, Free::KeepStorage);
+            self.pool.entry(layout).or_default().push(boxptr.cast());
+            self.pool_len += 1;
+            dead
+        } else {
+            (boxptr.as_ref().free)(boxptr, Free::All)
+        };
+        if let Some(dead) = dead {
+            self.dead_allocs.push(dead);
+        }
+    }
+
+    // Runs f, then frees whatever it allocated that isn't reachable from the roots or from
+    // objects that existed before the call. Older objects are left alone, reachable or not.
+    pub fn with_scope<R>(&mut self, f: impl FnOnce(&mut Arena) -> R) -> R {
+        // Generations only go up, so everything allocated in f will be at least this.
+        let first = NEXT_GENERATION.load(Ordering::Relaxed);
+        let result = f(self);
+        self.register_deferred_roots();
+        self.unmark_all();
+        let visitor = Visitor::new(ptr::null(), mem::take(&mut self.worklist));
+        self.mark_roots(&visitor);
+        self.scan_roots(&visitor);
+        for t in self.walk().filter(|t| unsafe { t.as_ref().generation() } < first) {
+            unsafe {
+                // SAFETY: List members are all live.
+                visitor.visit_ptr(t);
+            }
+        }
+        visitor.drain();
+        self.worklist = visitor.into_worklist();
+        let garbage: Vec<_> = self.walk().filter(|t| unsafe {
+            t.as_ref().generation() >= first && !t.as_ref().marked()
+        }).collect();
+        for t in garbage {
+            unsafe {
+                unlink(&mut self.start, t);
+                self.reclaim(t);
+            }
+        }
+        result
+    }
+}
+
+// Splice a box out of the list headed by *start. The box's own links are left alone, so an
+// ArenaIter that's already yielded it can carry on.
+unsafe fn unlink(start: &mut GcPtr, t: GcPtrNonNull) {
+    let boxptr = t.cast::<GcBox<()>>();
+    if ptr::eq(*start as *const (), t.as_ptr() as *const ()) {
+        *start = boxptr.as_ref().next;
+    }
+    let boxmut = boxptr.as_ptr().as_mut().unwrap();
+    if let Some(prevbox) = (boxmut.prev as *mut GcBox<()>).as_mut() {
+        prevbox.next = boxmut.next;
+    }
+    if let Some(nextbox) = (boxmut.next as *mut GcBox<()>).as_mut() {
+        nextbox.prev = boxmut.prev;
+    }
+}
+
+impl IdTable {
+    fn assign(&mut self, ptr: GcPtrNonNull) -> ObjId {
+        let index = match self.free.pop() {
+            Some(index) => index,
+            None => {
+                let index = u32::try_from(self.slots.len()).expect("out of object ids");
+                self.slots.push(IdSlot { ptr: None, tag: 0 });
+                index
+            },
+        };
+        let slot = &mut self.slots[index as usize];
+        slot.ptr = Some(ptr);
+        // SAFETY: The caller's just allocated it.
+        self.indices.insert(unsafe { ptr.as_ref().generation() }, index);
+        ObjId { index, tag: slot.tag }
+    }
+
+    // Frees the slot of the object with this generation, if it has one.
+    fn release(&mut self, generation: u64) {
+        let index = match self.indices.remove(&generation) {
+            Some(index) => index,
+            None => return,
+        };
+        let slot = &mut self.slots[index as usize];
+        slot.ptr = None;
+        slot.tag = slot.tag.wrapping_add(1);
+        self.free.push(index);
+    }
+}
+
+impl ObjId {
+    pub fn index(self) -> u32 {
+        self.index
+    }
+}
+
+impl FreedSet {
+    fn check(&self, _t: GcPtrNonNull) {
+        #[cfg(debug_assertions)]
+        if self.addrs.contains(&(_t.as_ptr() as *const () as usize)) {
+            panic!("sweep reached freed box {:p} again; the object list is corrupt", _t.as_ptr());
+        }
+    }
+
+    fn insert(&mut self, _t: GcPtrNonNull) {
+        #[cfg(debug_assertions)]
+        self.addrs.insert(_t.as_ptr() as *const () as usize);
+    }
+}
+
+// Everything goes, reachable or not; surviving Gc handles just see their objects collected.
+impl Drop for Arena {
+    fn drop(&mut self) {
+        self.clear();
+        self.run_deferred_drops();
+        self.release_pool();
+        self.dead_allocs.iter().for_each(release_dead_alloc);
+    }
+}
+
+impl Default for Arena {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// A summary; marks are counted as they are, which outside of mark and sweep is none.
+impl fmt::Debug for Arena {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Arena")
+            .field("len", &self.len())
+            .field("roots", &self.roots_len())
+            .field("marked", &self.walk().filter(|t| unsafe { t.as_ref().marked() }).count())
+            .finish()
+    }
+}
+
+// A stack of arenas per thread, for code that would rather not pass &mut Arena around. The free
+// functions below work on the top one. There's always at least one. None of them can be called
+// from inside another (say, from a finalizer run by collect)--the stack is borrowed throughout.
+thread_local! {
+    static ARENAS: RefCell<Vec<Arena>> = RefCell::new(vec![Arena::new()]);
+}
+
+pub fn with_arena<R>(f: impl FnOnce(&mut Arena) -> R) -> R {
+    ARENAS.with(|arenas| f(arenas.borrow_mut().last_mut().unwrap()))
+}
+
+pub fn push_arena() {
+    ARENAS.with(|arenas| arenas.borrow_mut().push(Arena::new()));
+}
+
+// Frees everything in the top arena, rooted or not, and goes back to the one under it.
+pub fn pop_arena() {
+    let popped = ARENAS.with(|arenas| {
+        let mut arenas = arenas.borrow_mut();
+        assert!(arenas.len() > 1, "can't pop the thread's last arena");
+        arenas.pop()
+    });
+    // Dropped outside the borrow, so finalizers could use the outer arena.
+    drop(popped);
+}
+
+pub fn gc<T: Trace + 'static>(value: T) -> Gc<T> {
+    with_arena(|arena| arena.gc(value))
+}
+
+pub fn root<T: Trace + 'static>(value: T) -> Gc<T> {
+    with_arena(|arena| arena.root(value))
+}
+
+pub fn collect() -> Collection {
+    with_arena(Arena::collect)
+}
+
+// Roots every value; mostly for terse setup in tests and examples.
+impl<T: Trace + 'static> FromIterator<T> for Arena {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut arena = Arena::new();
+        for value in iter {
+            arena.root(value);
+        }
+        arena
+    }
+}
+
+impl<'s> IntoIterator for &'s mut Arena {
+    type Item = GcPtrNonNull;
+    type IntoIter = ArenaIter<'s>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+// Note the use of associated methods because of Deref<Target=T>.
+impl<T: ?Sized> Gc<T> {
+    pub fn try_as_ref(this: &Self) -> Option<&T> {
+        unsafe {
+            // SAFETY: we're bounding the reference implicitly with the lifetime on self.
+            (*this.ptr.get()).inner.map(|pr| {
+                &pr.as_ref().value
+            })
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn as_ref(this: &Self) -> &T {
+        Self::try_as_ref(this).unwrap_or_else(|| {
+            panic!("Gc::as_ref on collected object of type {}", std::any::type_name::<T>())
+        })
+    }
+
+    /// Like `as_ref`, but without checking that the object is still alive.
+    ///
+    /// # Safety
+    ///
+    /// The object must not have been collected: it must be reachable from a root (or pinned) since
+    /// before the last collection. Calling this on a collected object is undefined behavior.
+    pub unsafe fn as_ref_unchecked(this: &Self) -> &T {
+        let inner = (*this.ptr.get()).inner;
+        debug_assert!(inner.is_some(), "Gc::as_ref_unchecked on collected object");
+        &inner.unwrap_unchecked().as_ref().value
+    }
+
+    pub fn try_as_mut(this: &mut Self) -> Option<&mut T> {
+        unsafe {
+            // SAFETY: As above; note the mutable borrow of self to statically guarantee
+            // uniqueness.
+            (*this.ptr.get()).inner.map(|mut pr| {
+                &mut pr.as_mut().value
+            })
+        }
+    }
+
+    // Both at once, for swaps and merges. As with as_mut, borrowing the handles mutably is what
+    // rules out other borrows through them; two handles to one object are refused.
+    pub fn borrow_two_mut<'a>(a: &'a mut Self, b: &'a mut Self) -> Result<(&'a mut T, &'a mut T), BorrowError> {
+        if Self::ptr_eq(a, b) {
+            return Err(BorrowError::SameObject);
+        }
+        match (Self::try_as_mut(a), Self::try_as_mut(b)) {
+            (Some(a), Some(b)) => Ok((a, b)),
+            _ => Err(BorrowError::Collected),
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn as_mut(this: &mut Self) -> &mut T {
+        Self::try_as_mut(this).unwrap_or_else(|| {
+            panic!("Gc::as_mut on collected object of type {}", std::any::type_name::<T>())
+        })
+    }
+
+    // Where the value is; like as_ref, this panics if it's been collected.
+    pub fn as_ptr(this: &Self) -> *const T {
+        Self::as_ref(this)
+    }
+
+    // Unlike clone, refuses to hand out another handle to a collected object.
+    pub fn try_clone(this: &Self) -> Option<Self> {
+        Self::try_as_ref(this).map(|_| this.clone())
+    }
+
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
         Rc::ptr_eq(&this.ptr, &other.ptr)
     }
+
+    // Compares the values, where == on Gc compares identity. Collected objects equal nothing.
+    pub fn value_eq(this: &Self, other: &Self) -> bool where T: PartialEq {
+        match (Self::try_as_ref(this), Self::try_as_ref(other)) {
+            (Some(a), Some(b)) => a == b,
+            _ => false,
+        }
+    }
+
+    // Swaps in a new value, handing back the old one. The handle's borrowed mutably, as for
+    // as_mut, so nothing borrowed through it can be alive; GcRefs through other handles are
+    // counted, and refuse it.
+    pub fn replace(this: &mut Self, value: T) -> Result<T, ReplaceError> where T: Sized {
+        let alloc = unsafe { *this.ptr.get() };
+        if alloc.inner.is_some() && alloc.borrows > 0 {
+            return Err(ReplaceError::Borrowed);
+        }
+        Self::try_as_mut(this).map(|old| mem::replace(old, value)).ok_or(ReplaceError::Collected)
+    }
+
+    // None if collected.
+    pub fn borrow(this: &Self) -> Option<GcRef<'_, T>> {
+        let value = Self::try_as_ref(this)?;
+        unsafe { (*this.ptr.get()).borrows += 1 };
+        Some(GcRef { value, alloc: &this.ptr })
+    }
+
+    pub fn downgrade(this: &Self) -> Weak<T> {
+        Weak {
+            ptr: Rc::downgrade(&this.ptr),
+        }
+    }
+
+    // Pins the object in place. Boxes never move once allocated--the list links them where they
+    // are, and the sweep drops the value in place before freeing it--so only moving the value out
+    // of its box could break the pin, and that takes a Gc (for as_mut, replace or try_unwrap).
+    // So this refuses, handing the Gc back, unless it's the only Gc or Weak to the object; the Pin
+    // and its clones never give a Gc back. Finalizers for T, which get a &mut T, skip it.
+    pub fn pin(this: Self) -> Result<Pin<Self>, Self> {
+        if Self::external_strong_count(&this) != 1 || Self::weak_count(&this) != 0 {
+            return Err(this);
+        }
+        unsafe {
+            (*this.ptr.get()).pinned = true;
+            // SAFETY: As above, nothing is left that could move the value.
+            Ok(Pin::new_unchecked(this))
+        }
+    }
+
+    // The handle inside a pinned Gc, for the crate's own use only: handing it out would let
+    // clones of it move the value.
+    fn unpinned(pinned: &Pin<Self>) -> &Self {
+        // SAFETY: Pin is repr(transparent).
+        unsafe { &*(pinned as *const Pin<Self> as *const Self) }
+    }
+
+    // Gc handles to this object, not counting the reference the arena keeps itself.
+    pub fn external_strong_count(this: &Self) -> usize {
+        let held = unsafe { (*this.ptr.get()).arena_held };
+        Rc::strong_count(&this.ptr) - usize::from(held)
+    }
+
+    // The Gc handles to this object, wherever they are--the same count as external_strong_count.
+    // The Rc also counts the arena's own reference, but whether there is one is down to the
+    // arena's bookkeeping (it's dropped on collection, unless Weaks are left), so it's left out.
+    pub fn strong_count(this: &Self) -> usize {
+        Self::external_strong_count(this)
+    }
+
+    // Weaks to this object still around, wherever they are.
+    pub fn weak_count(this: &Self) -> usize {
+        Rc::weak_count(&this.ptr)
+    }
+
+    pub fn weak_key(this: &Self) -> WeakKey {
+        unsafe {
+            // SAFETY: inner, if present, points to a live box.
+            (*this.ptr.get()).inner.map(|pr| WeakKey {
+                addr: pr.as_ptr() as *const () as usize,
+                generation: pr.as_ref().generation,
+            })
+        }.unwrap_or(WeakKey { addr: 0, generation: 0 })
+    }
+
+    // Which Arena (by Arena::id) the object lives in; None if collected or detached.
+    pub fn arena_id(this: &Self) -> Option<u64> {
+        unsafe {
+            // SAFETY: inner, if present, points to a live box.
+            (*this.ptr.get()).inner.map(|pr| pr.as_ref().arena_id)
+        }.filter(|&id| id != 0)
+    }
+
+    // None if collected.
+    pub fn inspect(this: &Self) -> Option<ObjectInfo> {
+        unsafe {
+            // SAFETY: inner, if present, points to a live box.
+            (*this.ptr.get()).inner.map(|pr| {
+                let gcbox = pr.cast::<GcBox<()>>().as_ref();
+                ObjectInfo {
+                    type_id: gcbox.type_id,
+                    type_name: gcbox.type_name,
+                    size: gcbox.size,
+                    epoch: gcbox.epoch,
+                    generation: gcbox.generation,
+                    survived_collections: gcbox.survived_collections,
+                    marked: gcbox.mark,
+                    rooted: gcbox.root_index.is_some() || gcbox.root_pending,
+                    strong_count: Self::external_strong_count(this),
+                }
+            })
+        }
+    }
+}
+
+impl<T: Any> Gc<T> {
+    // For reflection via std's downcasting; None if collected.
+    pub fn as_any(this: &Self) -> Option<&dyn Any> {
+        Self::try_as_ref(this).map(|value| value as &dyn Any)
+    }
+}
+
+impl<T> Gc<[T]> {
+    pub fn try_slice(this: &Self, range: Range<usize>) -> Option<GcSlice<'_, T>> {
+        Self::try_as_ref(this)
+            .and_then(|whole| whole.get(range))
+            .map(|slice| GcSlice { slice })
+    }
+
+    pub fn slice(this: &Self, range: Range<usize>) -> GcSlice<'_, T> {
+        let whole = Self::as_ref(this);
+        GcSlice { slice: &whole[range] }
+    }
+}
+
+impl<T: Trace + 'static> Gc<[T]> {
+    fn new_slice(items: Vec<T>) -> Self {
+        unsafe {
+            // SAFETY: The shims and type match the box exactly.
+            Gc::from_box(alloc_slice_box(
+                items, trace_slice::<T>, free_slice::<T>,
+                TypeId::of::<[T]>(), std::any::type_name::<[T]>(),
+            ))
+        }
+    }
+}
+
+impl Gc<str> {
+    fn new_str(s: &str) -> Self {
+        unsafe {
+            // SAFETY: str has the same layout (and metadata) as [u8], and free_str undoes exactly
+            // this.
+            let boxptr = alloc_slice_box(
+                s.as_bytes().to_vec(), trace_nothing, free_str,
+                TypeId::of::<str>(), std::any::type_name::<str>(),
+            ) as *mut GcBox<str>;
+            Gc::from_box(boxptr)
+        }
+    }
+}
+
+impl<T: ?Sized + 'static> Gc<T> {
+    // Wraps a box that has every header field written (alloc and detach will be overwritten),
+    // but isn't yet threaded into an arena.
+    unsafe fn from_box(boxptr: *mut GcBox<T>) -> Self {
+        let result = Self {
+            ptr: Rc::new(UnsafeCell::new(GcAlloc {
+                arena_held: true,
+                borrows: 0,
+                pinned: false,
+                inner: Some(NonNull::new_unchecked(boxptr)),
+            })),
+            marker: PhantomData,
+        };
+        // SAFETY: result.ptr is an UnsafeCell, so the compiler knows this can alias. No reference
+        // to the box is coined here at all--the write goes through the raw pointer.
+        ptr::addr_of_mut!((*boxptr).alloc).write(Some(Rc::clone(&result.ptr)));
+        ptr::addr_of_mut!((*boxptr).detach).write(detach_shim::<T>);
+        result
+    }
+}
+
+impl<T: 'static> Gc<T> {
+    // Moves the value out and frees the object, like Rc::try_unwrap, if it's live, in arena, and
+    // this is the only Gc to it (Weaks then see it as collected). Otherwise gives this back.
+    pub fn try_unwrap(this: Self, arena: &mut Arena) -> Result<T, Self> {
+        let inner = match unsafe { (*this.ptr.get()).inner } {
+            Some(inner) => inner,
+            None => return Err(this),
+        };
+        if Self::external_strong_count(&this) != 1 || unsafe { inner.as_ref().arena_id } != arena.id {
+            return Err(this);
+        }
+        arena.detach(&this);
+        unsafe {
+            // SAFETY: Live and now off the list; nothing else can reach the value, which is read
+            // out before the box is freed without it.
+            let value = ptr::read(&inner.as_ref().value);
+            arena.free_unwrapped(erase(inner));
+            Ok(value)
+        }
+    }
+}
+
+impl<T: Trace + 'static> Gc<T> {
+    fn new(value: T) -> Self {
+        unsafe {
+            // SAFETY: The box is fresh, and its shims are T's.
+            Gc::from_box(Self::new_box(value, free_value::<T>))
+        }
+    }
+
+    // Boxes value as a T, but hands out a Gc<U> for some unsized U that T coerces to. coerce is
+    // just |p| p at the call site, where the compiler knows how.
+    fn new_unsized<U: ?Sized + 'static>(value: T, coerce: fn(*mut T) -> *mut U) -> Gc<U> {
+        let boxptr = Self::new_box(value, free_coerced::<T, U>);
+        unsafe {
+            // SAFETY: U has T's alignment, so repr(C) puts the value at the same offset in a
+            // GcBox<U>; the coercion only adds the metadata.
+            let value = coerce(ptr::addr_of_mut!((*boxptr).value));
+            Gc::from_box(value.wrapping_byte_sub(mem::offset_of!(GcBox<T>, value)) as *mut GcBox<U>)
+        }
+    }
+
+    fn new_box(value: T, free: FreeShim) -> *mut GcBox<T> {
+        Box::into_raw(Box::new(Self::fresh_box(value, free)))
+    }
+
+    // A box with every header field written (alloc will be overwritten).
+    fn fresh_box(value: T, free: FreeShim) -> GcBox<T> {
+        GcBox {
+            mark: false,
+            next: null_gcptr(),
+            prev: null_gcptr(),
+            trace: trace_value::<T>,
+            free,
+            detach: detach_shim::<T>,
+            alloc: None,
+            generation: next_generation(),
+            len: 0,
+            type_id: TypeId::of::<T>(),
+            type_name: std::any::type_name::<T>(),
+            survived_collections: 0,
+            size: mem::size_of::<GcBox<T>>(),
+            epoch: 0,
+            root_pending: false,
+            root_index: None,
+            arena_id: 0,
+            layout: Some(Layout::new::<GcBox<T>>()),
+            value
+        }
+    }
+}
+
+impl<T: ?Sized> Clone for Gc<T> {
+    fn clone(&self) -> Self {
+        Self {
+            ptr: Rc::clone(&self.ptr),
+            marker: PhantomData,
+        }
+    }
+}
+
+// Comparisons are by identity (the alloc), not by value. The ordering is by address: arbitrary,
+// but consistent for as long as the handles live, and not stable from one run to the next.
+impl<T: ?Sized> PartialEq for Gc<T> {
+    fn eq(&self, other: &Self) -> bool {
+        Gc::ptr_eq(self, other)
+    }
+}
+
+impl<T: ?Sized> Eq for Gc<T> {}
+
+impl<T: ?Sized> PartialOrd for Gc<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T: ?Sized> Ord for Gc<T> {
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        (Rc::as_ptr(&self.ptr) as *const ()).cmp(&(Rc::as_ptr(&other.ptr) as *const ()))
+    }
+}
+
+impl<T: ?Sized> Hash for Gc<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (Rc::as_ptr(&self.ptr) as *const ()).hash(state);
+    }
+}
+
+// Weaks compare by identity too, and hash the same as Gcs to the same object. The weak count
+// keeps the alloc's address from being reused, even after the object is gone.
+impl<T: ?Sized> PartialEq for Weak<T> {
+    fn eq(&self, other: &Self) -> bool {
+        Self::ptr_eq(self, other)
+    }
+}
+
+impl<T: ?Sized> Eq for Weak<T> {}
+
+impl<T: ?Sized> PartialEq<Gc<T>> for Weak<T> {
+    fn eq(&self, other: &Gc<T>) -> bool {
+        ptr::eq(self.ptr.as_ptr() as *const (), Rc::as_ptr(&other.ptr) as *const ())
+    }
+}
+
+impl<T: ?Sized> PartialEq<Weak<T>> for Gc<T> {
+    fn eq(&self, other: &Weak<T>) -> bool {
+        other == self
+    }
+}
+
+impl<T: ?Sized> Hash for Weak<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (self.ptr.as_ptr() as *const ()).hash(state);
+    }
+}
+
+impl<T: ?Sized> Deref for Gc<T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target { Gc::as_ref(self) }
+}
+
+impl<T: ?Sized> DerefMut for Gc<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target { Gc::as_mut(self) }
+}
+
+// A Gc held by a traced value is an edge in the graph.
+impl<T: ?Sized> Trace for Gc<T> {
+    fn trace(&self, visitor: &Visitor) {
+        visitor.visit(self);
+    }
+}
+
+impl<T: ?Sized> Trace for Pin<Gc<T>> {
+    fn trace(&self, visitor: &Visitor) {
+        visitor.visit(Gc::unpinned(self));
+    }
+}
+
+impl<T: ?Sized> Weak<T> {
+    pub fn upgrade(&self) -> Option<Gc<T>> {
+        let ptr = self.ptr.upgrade()?;
+        // The Rc outliving its object is the collected case.
+        let inner = unsafe { (*ptr.get()).inner };
+        inner.map(|_| Gc {
+            ptr,
+            marker: PhantomData,
+        })
+    }
+
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        this.ptr.ptr_eq(&other.ptr)
+    }
+}
+
+impl<T: ?Sized> Clone for Weak<T> {
+    fn clone(&self) -> Self {
+        Self {
+            ptr: rc::Weak::clone(&self.ptr),
+        }
+    }
 }
 
-impl<T: Trace> Gc<T> {
-    fn new(value: T) -> Self {
-        let result = Self {
-            ptr: Rc::new(UnsafeCell::new(GcAlloc {
-                inner: Some(Box::leak(
-                         Box::new(GcBox {
-                             mark: false,
-                             next: null_gcptr(),
-                             prev: null_gcptr(),
-                             meta: extract_meta(&value as &dyn Trace),
-                             alloc: ptr::null_mut(),
-                             value
-                         })
-                    ).into()),
-                })),
-            marker: PhantomData,
-        };
+// Allocates a value in an arena behind an unsized Gc, such as a Gc<dyn Trait>, whatever the
+// expected type is: gc_dyn!(arena, value).
+#[macro_export]
+macro_rules! gc_dyn {
+    ($arena:expr, $value:expr) => {{
+        let arena: &mut $crate::Arena = $arena;
+        let value = $value;
         unsafe {
-            // SAFETY: result.ptr is an UnsafeCell, so the compiler knows this can alias. In
-            // addtion, only one reference (a mutable one) is coined here, and dropped by this
-            // block--the provnenance doesn't include the *mut coming out of UnsafeCell::get().
-            (*result.ptr.get()).inner.unwrap().as_mut().alloc =
-                result.ptr.get();
+            // SAFETY: The coercion is the compiler's.
+            arena.gc_unsized(value, |p| p)
+        }
+    }};
+}
+
+// Leaves: nothing in these can refer to the heap.
+macro_rules! trace_nothing {
+    ($($t:ty),*) => {
+        $(
+            impl Trace for $t {
+                fn trace(&self, _visitor: &Visitor) {}
+            }
+        )*
+    };
+}
+
+trace_nothing!(
+    (), bool, char, u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64,
+    str, String
+);
+
+impl<T: Trace> Trace for Option<T> {
+    fn trace(&self, visitor: &Visitor) {
+        if let Some(item) = self {
+            item.trace(visitor);
+        }
+    }
+}
+
+impl<T: Trace> Trace for [T] {
+    fn trace(&self, visitor: &Visitor) {
+        for item in self {
+            item.trace(visitor);
+        }
+    }
+}
+
+impl<T: Trace> Trace for Vec<T> {
+    fn trace(&self, visitor: &Visitor) {
+        for item in self {
+            item.trace(visitor);
+        }
+    }
+}
+
+// Keys are assumed not to refer to the heap; they're hashed or ordered, so shouldn't change anyway.
+impl<K, V: Trace, S> Trace for HashMap<K, V, S> {
+    fn trace(&self, visitor: &Visitor) {
+        for value in self.values() {
+            value.trace(visitor);
+        }
+    }
+}
+
+impl<K, V: Trace> Trace for BTreeMap<K, V> {
+    fn trace(&self, visitor: &Visitor) {
+        for value in self.values() {
+            value.trace(visitor);
         }
-        result
     }
 }
 
-impl<T> Clone for Gc<T> {
+macro_rules! trace_tuple {
+    ($($name:ident)+) => {
+        impl<$($name: Trace),+> Trace for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn trace(&self, visitor: &Visitor) {
+                let ($($name,)+) = self;
+                $($name.trace(visitor);)+
+            }
+        }
+    };
+}
+
+trace_tuple!(A);
+trace_tuple!(A B);
+trace_tuple!(A B C);
+trace_tuple!(A B C D);
+trace_tuple!(A B C D E);
+trace_tuple!(A B C D E F);
+trace_tuple!(A B C D E F G);
+trace_tuple!(A B C D E F G H);
+trace_tuple!(A B C D E F G H I);
+trace_tuple!(A B C D E F G H I J);
+trace_tuple!(A B C D E F G H I J K);
+trace_tuple!(A B C D E F G H I J K L);
+
+impl<T: ?Sized + Trace> Trace for Box<T> {
+    fn trace(&self, visitor: &Visitor) {
+        T::trace(self, visitor);
+    }
+}
+
+impl<T: Trace> Trace for VecDeque<T> {
+    fn trace(&self, visitor: &Visitor) {
+        for item in self {
+            item.trace(visitor);
+        }
+    }
+}
+
+impl<T: Trace> Trace for LinkedList<T> {
+    fn trace(&self, visitor: &Visitor) {
+        for item in self {
+            item.trace(visitor);
+        }
+    }
+}
+
+impl<T: ?Sized> SharedGc<T> {
+    pub fn borrow(&self) -> Ref<'_, Gc<T>> {
+        self.slot.gc.borrow()
+    }
+
+    // Repoints every clone of this handle at gc, which becomes the root in place of the old
+    // target. A collected gc simply leaves nothing rooted.
+    pub fn set(&self, gc: Gc<T>) {
+        let inner = unsafe { (*gc.ptr.get()).inner };
+        let mut table = self.slot.table.borrow_mut();
+        match inner {
+            Some(inner) => table.roots.insert(self.slot.id, erase(inner)),
+            None => table.roots.remove(&self.slot.id),
+        };
+        table.repointed = true;
+        *self.slot.gc.borrow_mut() = gc;
+    }
+}
+
+impl<T: ?Sized> Clone for SharedGc<T> {
     fn clone(&self) -> Self {
         Self {
-            ptr: Rc::clone(&self.ptr),
-            marker: PhantomData,
+            slot: Rc::clone(&self.slot),
+        }
+    }
+}
+
+impl<T: Trace + Clone + 'static> GcCow<T> {
+    pub fn make_mut(&mut self, arena: &mut Arena) -> &mut T {
+        // Every other handle--in another GcCow, or anywhere in the heap--is a strong count.
+        if Gc::external_strong_count(&self.gc) > 1 {
+            let copy = arena.gc(T::clone(&self.gc));
+            // A root moves over to the copy, with the handle being written through.
+            let rooted = unsafe { *self.gc.ptr.get() }.inner.is_some_and(|pr| unsafe {
+                // SAFETY: inner, if present, points to a live box.
+                pr.as_ref().root_index.is_some() || pr.as_ref().root_pending
+            });
+            if rooted {
+                arena.unroot(&self.gc);
+                arena.make_root(&copy);
+            }
+            self.gc = copy;
+        }
+        Gc::as_mut(&mut self.gc)
+    }
+}
+
+impl<T> GcCow<T> {
+    pub fn as_gc(&self) -> &Gc<T> {
+        &self.gc
+    }
+}
+
+impl<T> Clone for GcCow<T> {
+    fn clone(&self) -> Self {
+        Self {
+            gc: self.gc.clone(),
         }
     }
 }
 
-impl<T> Deref for Gc<T> {
+impl<T> Deref for GcCow<T> {
     type Target = T;
-    fn deref(&self) -> &Self::Target { Gc::as_ref(self) }
+    fn deref(&self) -> &T { &self.gc }
 }
 
-impl<T> DerefMut for Gc<T> {
-    fn deref_mut(&mut self) -> &mut Self::Target { Gc::as_mut(self) }
+impl<T> GcCell<T> {
+    pub fn new(value: T) -> Self {
+        GcCell {
+            cell: RefCell::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.cell.into_inner()
+    }
+}
+
+impl<T: ?Sized> GcCell<T> {
+    // Panics if it's mutably borrowed.
+    pub fn borrow(&self) -> Ref<'_, T> {
+        self.cell.borrow()
+    }
+
+    // Panics if it's borrowed at all.
+    pub fn borrow_mut(&self) -> RefMut<'_, T> {
+        self.cell.borrow_mut()
+    }
+
+    pub fn try_borrow(&self) -> Option<Ref<'_, T>> {
+        self.cell.try_borrow().ok()
+    }
+
+    pub fn try_borrow_mut(&self) -> Option<RefMut<'_, T>> {
+        self.cell.try_borrow_mut().ok()
+    }
+}
+
+impl<T: ?Sized + Trace> Trace for GcCell<T> {
+    fn trace(&self, visitor: &Visitor) {
+        if let Ok(value) = self.cell.try_borrow() {
+            value.trace(visitor);
+        }
+    }
+}
+
+impl<T> Trace for GcCow<T> {
+    fn trace(&self, visitor: &Visitor) {
+        visitor.visit(&self.gc);
+    }
+}
+
+impl<T> Trace for WithTraceFn<T> {
+    fn trace(&self, visitor: &Visitor) {
+        (self.trace_fn)(&self.value, visitor);
+    }
+}
+
+impl<'a, T: Trace + 'static, F: FnMut(Vec<Gc<T>>) -> T> TreeBuilder<'a, T, F> {
+    fn add(&mut self, value: T) {
+        let gc = self.arena.gc(value);
+        self.arena.make_root(&gc);
+        self.open.last_mut().unwrap().push(gc.clone());
+        self.temps.push(gc);
+    }
+
+    pub fn leaf(mut self, value: T) -> Self {
+        self.add(value);
+        self
+    }
+
+    pub fn begin(mut self) -> Self {
+        self.open.push(Vec::new());
+        self
+    }
+
+    pub fn end(mut self) -> Self {
+        if self.open.len() < 2 {
+            panic!("TreeBuilder::end without a matching begin");
+        }
+        let children = self.open.pop().unwrap();
+        let value = (self.make)(children);
+        self.add(value);
+        self
+    }
+
+    // The root comes back rooted; nothing else built here is.
+    pub fn finish(mut self) -> Gc<T> {
+        if self.open.len() != 1 {
+            panic!("TreeBuilder::finish with {} unended node(s)", self.open.len() - 1);
+        }
+        let children = self.open.pop().unwrap();
+        let root = self.arena.gc((self.make)(children));
+        self.arena.make_root(&root);
+        for temp in &self.temps {
+            self.arena.unroot(temp);
+        }
+        root
+    }
+}
+
+impl<T: ?Sized> Drop for SharedSlot<T> {
+    fn drop(&mut self) {
+        self.table.borrow_mut().roots.remove(&self.id);
+    }
+}
+
+impl Drop for RootGuard {
+    fn drop(&mut self) {
+        self.table.borrow_mut().roots.remove(&self.id);
+    }
+}
+
+// Weaks aren't edges; that's the point.
+impl<T: ?Sized> Trace for Weak<T> {
+    fn trace(&self, _visitor: &Visitor) {}
+}
+
+impl<T: ?Sized> WeakList<T> {
+    pub fn new() -> Self {
+        Self {
+            items: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, gc: &Gc<T>) {
+        self.items.push(Gc::downgrade(gc));
+    }
+
+    // Counts entries not yet pruned, dead or alive.
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn prune(&mut self) {
+        self.items.retain(|weak| weak.upgrade().is_some());
+    }
+
+    pub fn iter_live(&mut self) -> impl Iterator<Item = Gc<T>> + '_ {
+        self.prune();
+        self.items.iter().filter_map(Weak::upgrade)
+    }
+}
+
+impl<T: ?Sized> Default for WeakList<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: ?Sized> Trace for WeakList<T> {
+    fn trace(&self, _visitor: &Visitor) {}
+}
+
+impl<T: Trace + 'static> GcList<T> {
+    pub fn new() -> Self {
+        Self {
+            head: None,
+            len: 0,
+        }
+    }
+
+    pub fn push(&mut self, arena: &mut Arena, value: T) {
+        let next = self.head.take();
+        self.head = Some(arena.gc(ListNode { value, next }));
+        self.len += 1;
+    }
+
+    // The node stays in the arena (other lists may share it), so this hands back a copy.
+    pub fn pop(&mut self) -> Option<T> where T: Clone {
+        let head = self.head.take()?;
+        let node = Gc::as_ref(&head);
+        self.head = node.next.clone();
+        self.len -= 1;
+        Some(node.value.clone())
+    }
+
+    pub fn head(&self) -> Option<&Gc<ListNode<T>>> {
+        self.head.as_ref()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head.is_none()
+    }
+
+    pub fn iter(&self) -> GcListIter<'_, T> {
+        GcListIter {
+            cur: self.head.as_ref(),
+        }
+    }
+}
+
+impl<T: Trace + 'static> Default for GcList<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Trace for GcList<T> {
+    fn trace(&self, visitor: &Visitor) {
+        if let Some(head) = &self.head {
+            visitor.visit(head);
+        }
+    }
+}
+
+impl<T> ListNode<T> {
+    pub fn value(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T: Trace> Trace for ListNode<T> {
+    fn trace(&self, visitor: &Visitor) {
+        self.value.trace(visitor);
+        if let Some(next) = &self.next {
+            visitor.visit(next);
+        }
+    }
+}
+
+impl<'a, T> Iterator for GcListIter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        let node = Gc::as_ref(self.cur?);
+        self.cur = node.next.as_ref();
+        Some(&node.value)
+    }
+}
+
+impl<H> GcResource<H> {
+    fn close(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            (self.close)(handle);
+        }
+    }
+}
+
+impl<H> Gc<GcResource<H>> {
+    pub fn with_handle<R>(this: &Self, f: impl FnOnce(&H) -> R) -> Result<R, ResourceError> {
+        Self::try_as_ref(this)
+            .and_then(|resource| resource.handle.as_ref())
+            .map(f)
+            .ok_or(ResourceError::Collected)
+    }
+}
+
+// In case it's freed without being collected, as by a detached object's leak being plugged.
+impl<H> Drop for GcResource<H> {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+impl<H> Trace for GcResource<H> {
+    fn trace(&self, _visitor: &Visitor) {}
 }
 
 impl<T: ?Sized> Sealed for GcBox<T> {}
@@ -312,6 +3448,23 @@ impl<T: ?Sized> Traverse for GcBox<T> {
     fn marked(&self) -> bool { self.mark }
     fn next(&self) -> GcPtr { self.next }
     fn prev(&self) -> GcPtr { self.prev }
+    fn generation(&self) -> u64 { self.generation }
+}
+
+impl<'a, T> Deref for GcSlice<'a, T> {
+    type Target = [T];
+    fn deref(&self) -> &Self::Target { self.slice }
+}
+
+impl<'a, T: ?Sized> Deref for GcRef<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target { self.value }
+}
+
+impl<'a, T: ?Sized> Drop for GcRef<'a, T> {
+    fn drop(&mut self) {
+        unsafe { (*self.alloc.get()).borrows -= 1 };
+    }
 }
 
 impl<'a> Iterator for ArenaIter<'a> {
@@ -331,6 +3484,53 @@ impl<'a> Iterator for ArenaIter<'a> {
     }
 }
 
+impl fmt::Display for ReplaceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplaceError::Collected => write!(f, "replace on collected object"),
+            ReplaceError::Borrowed => write!(f, "replace on borrowed object"),
+        }
+    }
+}
+
+impl std::error::Error for ReplaceError {}
+
+impl fmt::Display for BorrowError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BorrowError::SameObject => write!(f, "borrow_two_mut on one object twice"),
+            BorrowError::Collected => write!(f, "borrow_two_mut on collected object"),
+        }
+    }
+}
+
+impl std::error::Error for BorrowError {}
+
+impl fmt::Display for ResourceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ResourceError::Collected => write!(f, "with_handle on collected resource"),
+        }
+    }
+}
+
+impl std::error::Error for ResourceError {}
+
+impl fmt::Display for HeapLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "heap limit exceeded")
+    }
+}
+
+impl std::error::Error for HeapLimitExceeded {}
+
+impl GcHandle {
+    // O(heap), as for Arena::resolve.
+    pub fn resolve(&self, arena: &Arena) -> Option<GcPtrNonNull> {
+        arena.find_live(self.ptr.as_ptr() as *const () as usize, self.generation)
+    }
+}
+
 // Manual impls to avoid constraints on the underlying T
 impl<T: ?Sized> Clone for GcAlloc<T> {
     fn clone(&self) -> Self {
@@ -341,24 +3541,119 @@ impl<T: ?Sized> Clone for GcAlloc<T> {
 impl<T: ?Sized> Copy for GcAlloc<T> {}
 
 impl Visitor {
-    pub fn visit<T>(&self, gc: &Gc<T>) {
-        let gcbox = unsafe {
+    fn new(cancel: *const AtomicBool, worklist: Vec<NonNull<GcBox<()>>>) -> Self {
+        Self {
+            _not_pub_constructable: (),
+            cancel,
+            worklist: RefCell::new(worklist),
+            recording: false,
+        }
+    }
+
+    fn recording(worklist: Vec<NonNull<GcBox<()>>>) -> Self {
+        Self {
+            recording: true,
+            ..Self::new(ptr::null(), worklist)
+        }
+    }
+
+    // Hands the worklist back, emptied but with its capacity intact.
+    fn into_worklist(self) -> Vec<NonNull<GcBox<()>>> {
+        let mut worklist = self.worklist.into_inner();
+        worklist.clear();
+        worklist
+    }
+
+    // Traces pending boxes until none are left (or the collection is cancelled).
+    fn drain(&self) {
+        while !self.cancelled() {
+            // Don't hold the borrow across the trace; it pushes more.
+            let next = self.worklist.borrow_mut().pop();
+            let gcbox = match next {
+                Some(gcbox) => gcbox,
+                None => break,
+            };
+            unsafe {
+                // SAFETY: Begin your dragon prayers.
+                // We've sealed Traverse as a trait, so we know our implementors (and it's only
+                // GcBox).
+                // The type we chose for T is definitely wrong, but only the header is read through
+                // it; the shim recovers the real type for the value.
+                (gcbox.as_ref().trace)(gcbox, self);
+            }
+        }
+    }
+
+    // Traces at most budget pending boxes; returns whether that was all of them.
+    fn drain_some(&self, budget: usize) -> bool {
+        for _ in 0..budget {
+            let next = self.worklist.borrow_mut().pop();
+            match next {
+                Some(gcbox) => unsafe {
+                    // SAFETY: As in drain.
+                    (gcbox.as_ref().trace)(gcbox, self);
+                },
+                None => return true,
+            }
+        }
+        self.worklist.borrow().is_empty()
+    }
+
+    fn cancelled(&self) -> bool {
+        unsafe {
+            // SAFETY: The pointer, if any, came from a borrow that outlives the collection.
+            self.cancel.as_ref()
+        }.is_some_and(|c| c.load(Ordering::Relaxed))
+    }
+
+    pub fn visit<T: ?Sized>(&self, gc: &Gc<T>) {
+        // The marks will be thrown away, so stop descending.
+        if self.cancelled() {
+            return;
+        }
+        let inner = unsafe {
             // SAFETY: Rely on this being constructed and not dropped.
             // Aliasing: Rust would normally complain about seeking a &mut from our &Gc, but the
             // underlying raw pointer is represented as mutable.
-            (*gc.ptr.get()).inner.unwrap().as_mut()
+            (*gc.ptr.get()).inner
+        };
+        // A handle to something already collected (say, one that was behind a GcCell borrowed
+        // mutably through a collection) isn't an edge any more.
+        let mut gcbox = match inner {
+            Some(inner) => inner.cast::<GcBox<()>>(),
+            None => return,
         };
-        if gcbox.mark {
+        if self.recording {
+            self.worklist.borrow_mut().push(gcbox);
             return;
         }
-        gcbox.mark = true;
         unsafe {
-            // SAFETY: See Arena::collect.
-            let tobj: &dyn Trace = std::mem::transmute(
-                (&gcbox.value, gcbox.meta)
-            );
-            tobj.trace(&self);
+            if gcbox.as_ref().mark {
+                return;
+            }
+            gcbox.as_mut().mark = true;
+        }
+        self.worklist.borrow_mut().push(gcbox);
+    }
+
+    /// Marks and traces an object through a raw box pointer, for structures that store
+    /// `GcPtrNonNull` rather than `Gc<T>`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to a live box belonging to the arena being collected--one yielded by that
+    /// arena's iterator (or resolved through a `GcHandle`) and not freed since.
+    pub unsafe fn visit_ptr(&self, mut ptr: GcPtrNonNull) {
+        if self.recording {
+            self.worklist.borrow_mut().push(ptr.cast::<GcBox<()>>());
+            return;
+        }
+        if self.cancelled() || ptr.as_ref().marked() {
+            return;
         }
+        ptr.as_mut().mark();
+        // This cast intentionally discards the Traverse vtable--we won't need it again.
+        self.worklist.borrow_mut().push(ptr.cast::<GcBox<()>>());
     }
 }
 
@@ -0,0 +1,203 @@
+// Object graphs written out and read back with their sharing and cycles intact. Each object is
+// written in full the first time it's reached, as (id, Some(value)), and as just (id, None) from
+// then on--including from inside itself, which is where a cycle ends. Reading back, the first
+// sight of an id makes its Gc, not yet attached to any box, and every reference to the id shares
+// it; once the object's value has been read, its box is allocated and threaded into that Gc.
+
+use super::*;
+
+use std::collections::hash_map::Entry;
+
+use serde::de::{self, Deserialize, Deserializer, SeqAccess};
+use serde::ser::{self, Serialize, SerializeTuple, Serializer};
+
+thread_local! {
+    // Box address to id, for everything written so far.
+    static WRITING: RefCell<Option<HashMap<usize, u64>>> = const { RefCell::new(None) };
+    static READING: RefCell<Option<Reading>> = const { RefCell::new(None) };
+}
+
+struct Reading {
+    arena: *mut Arena,
+    // The Gc alloc for each id seen so far, as an Rc<UnsafeCell<GcAlloc<T>>> for its T.
+    objects: HashMap<u64, Rc<dyn Any>>,
+    defined: HashSet<u64>,
+}
+
+// Ends the session it started, if any, however the serialization ends.
+struct WritingSession(bool);
+
+// Ends the session, and puts back the arena's thresholds and any session it interrupted.
+struct ReadingSession {
+    arena: *mut Arena,
+    auto_collect: (Option<usize>, Option<usize>),
+    outer: Option<Reading>,
+}
+
+// Writes value, sharing objects between every Gc in it. A Gc serialized on its own shares only
+// within itself.
+pub fn serialize_graph<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+    where T: Serialize + ?Sized, S: Serializer
+{
+    let _session = WritingSession::join();
+    value.serialize(serializer)
+}
+
+// Reads a value written by serialize_graph, allocating its objects in arena, unrooted. Nothing is
+// collected automatically while it's underway, since what's been read so far isn't reachable
+// from anything yet.
+pub fn deserialize_graph<'de, T, D>(arena: &mut Arena, deserializer: D) -> Result<T, D::Error>
+    where T: Deserialize<'de>, D: Deserializer<'de>
+{
+    let _session = ReadingSession::start(arena);
+    let value = T::deserialize(deserializer)?;
+    // Fixup: every id referred to must have turned up with its value somewhere.
+    READING.with(|reading| {
+        let reading = reading.borrow();
+        let reading = reading.as_ref().unwrap();
+        if reading.objects.len() == reading.defined.len() {
+            Ok(value)
+        } else {
+            Err(de::Error::custom("reference to an object that's never written out"))
+        }
+    })
+}
+
+impl WritingSession {
+    fn join() -> Self {
+        WRITING.with(|writing| {
+            let mut writing = writing.borrow_mut();
+            let started = writing.is_none();
+            if started {
+                *writing = Some(HashMap::new());
+            }
+            WritingSession(started)
+        })
+    }
+}
+
+impl Drop for WritingSession {
+    fn drop(&mut self) {
+        if self.0 {
+            WRITING.with(|writing| *writing.borrow_mut() = None);
+        }
+    }
+}
+
+impl ReadingSession {
+    fn start(arena: &mut Arena) -> Self {
+        let auto_collect = (arena.auto_collect.take(), arena.auto_collect_bytes.take());
+        let arena = arena as *mut Arena;
+        let outer = READING.with(|reading| reading.borrow_mut().replace(Reading {
+            arena,
+            objects: HashMap::new(),
+            defined: HashSet::new(),
+        }));
+        ReadingSession { arena, auto_collect, outer }
+    }
+}
+
+impl Drop for ReadingSession {
+    fn drop(&mut self) {
+        // Dropped only once the Reading's done with, after which nothing else holds the pointer.
+        let arena = unsafe { &mut *self.arena };
+        arena.auto_collect = self.auto_collect.0;
+        arena.auto_collect_bytes = self.auto_collect.1;
+        let outer = self.outer.take();
+        // Taken out before dropping, since the unused Gcs it holds could be anything.
+        let done = READING.with(|reading| mem::replace(&mut *reading.borrow_mut(), outer));
+        drop(done);
+    }
+}
+
+impl<T: Serialize + ?Sized> Serialize for Gc<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let value = Gc::try_as_ref(self)
+            .ok_or_else(|| ser::Error::custom("can't serialize a collected object"))?;
+        let _session = WritingSession::join();
+        // Noted before the value's written, so references back to it from inside find it.
+        let (id, first) = WRITING.with(|writing| {
+            let mut writing = writing.borrow_mut();
+            let ids = writing.as_mut().unwrap();
+            let next = ids.len() as u64;
+            match ids.entry(value as *const T as *const () as usize) {
+                Entry::Occupied(entry) => (*entry.get(), false),
+                Entry::Vacant(entry) => (*entry.insert(next), true),
+            }
+        });
+        let mut tuple = serializer.serialize_tuple(2)?;
+        tuple.serialize_element(&id)?;
+        tuple.serialize_element(&Some(value).filter(|_| first))?;
+        tuple.end()
+    }
+}
+
+impl<'de, T: Deserialize<'de> + Trace + 'static> Deserialize<'de> for Gc<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_tuple(2, GcVisitor(PhantomData))
+    }
+}
+
+struct GcVisitor<T>(PhantomData<T>);
+
+impl<'de, T: Deserialize<'de> + Trace + 'static> de::Visitor<'de> for GcVisitor<T> {
+    type Value = Gc<T>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "an object id, and the object the first time it appears")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Gc<T>, A::Error> {
+        let id: u64 = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let gc = shared_handle::<T>(id).map_err(de::Error::custom)?;
+        let value: Option<T> = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(1, &self))?;
+        if let Some(value) = value {
+            define(&gc, id, value).map_err(de::Error::custom)?;
+        }
+        Ok(gc)
+    }
+}
+
+// The Gc every reference to id shares, attached to its box only once the value's been read.
+fn shared_handle<T: 'static>(id: u64) -> Result<Gc<T>, &'static str> {
+    READING.with(|reading| {
+        let mut reading = reading.borrow_mut();
+        let reading = reading.as_mut().ok_or("Gc deserialized outside of deserialize_graph")?;
+        let alloc = reading.objects.entry(id).or_insert_with(|| {
+            Rc::new(UnsafeCell::new(GcAlloc::<T> {
+                arena_held: false,
+                borrows: 0,
+                pinned: false,
+                inner: None,
+            }))
+        });
+        let ptr = Rc::clone(alloc).downcast::<UnsafeCell<GcAlloc<T>>>()
+            .map_err(|_| "one object id used for two types")?;
+        Ok(Gc { ptr, marker: PhantomData })
+    })
+}
+
+fn define<T: Trace + 'static>(gc: &Gc<T>, id: u64, value: T) -> Result<(), &'static str> {
+    let arena = READING.with(|reading| {
+        let mut reading = reading.borrow_mut();
+        let reading = reading.as_mut().unwrap();
+        if !reading.defined.insert(id) {
+            return Err("one object id written out twice");
+        }
+        Ok(reading.arena)
+    })?;
+    // SAFETY: deserialize_graph holds the arena for as long as the Reading is around, and
+    // doesn't touch it itself.
+    let fresh = unsafe { &mut *arena }.gc(value);
+    unsafe {
+        // SAFETY: fresh is the only handle to its new box, which now takes gc's alloc instead;
+        // fresh's goes away with fresh.
+        let mut inner = (*fresh.ptr.get()).inner.take().unwrap();
+        (*fresh.ptr.get()).arena_held = false;
+        let alloc = &mut *gc.ptr.get();
+        alloc.inner = Some(inner);
+        alloc.arena_held = true;
+        inner.as_mut().alloc = Some(Rc::clone(&gc.ptr));
+    }
+    Ok(())
+}
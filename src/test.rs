@@ -1,6 +1,45 @@
 use super::*;
 
 use std::fmt::{self, Debug, Formatter};
+use std::alloc::{GlobalAlloc, System};
+use std::cell::Cell;
+
+// Counts allocations per thread, so tests running in parallel don't see each other's.
+struct CountingAlloc;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };  // including reallocations
+    static LIVE: Cell<isize> = const { Cell::new(0) };  // blocks not yet freed
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+        let _ = LIVE.try_with(|n| n.set(n.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let _ = LIVE.try_with(|n| n.set(n.get() - 1));
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAlloc = CountingAlloc;
+
+fn allocations() -> usize {
+    ALLOCATIONS.with(Cell::get)
+}
+
+fn live_allocations() -> isize {
+    LIVE.with(Cell::get)
+}
 
 // XXX don't try this at home; I'm doing this because (in the tests) I don't feel like introducing
 // lifetime parameters.
@@ -15,6 +54,7 @@ impl Drop for RunOnDrop {
     }
 }
 
+#[allow(dead_code)]  // RunOnDrop's field is only there to be dropped
 enum Object {
     Simple,
     Container(Vec<Gc<Object>>),
@@ -22,6 +62,7 @@ enum Object {
 }
 
 // This implementation is intentionally minimal for the uses of these test cases.
+#[allow(clippy::match_like_matches_macro)]
 impl PartialEq for Object {
     fn eq(&self, other: &Self) -> bool {
         use Object::*;
@@ -62,8 +103,8 @@ impl Trace for Object {
 #[test]
 fn doesnt_free_roots() {
     let mut arena = Arena::new();
-    let a = arena.root(Object::Simple);
-    let b = arena.root(Object::Simple);
+    let _a = arena.root(Object::Simple);
+    let _b = arena.root(Object::Simple);
     let col = arena.collect();
     assert_eq!(col.total, 2);
     assert_eq!(col.collected, 0);
@@ -72,8 +113,8 @@ fn doesnt_free_roots() {
 #[test]
 fn frees_unrooted() {
     let mut arena = Arena::new();
-    let a = arena.gc(Object::Simple);
-    let b = arena.gc(Object::Simple);
+    let _a = arena.gc(Object::Simple);
+    let _b = arena.gc(Object::Simple);
     let col = arena.collect();
     assert_eq!(col.total, 2);
     assert_eq!(col.collected, 2);
@@ -83,8 +124,8 @@ fn frees_unrooted() {
 fn visits_children() {
     let mut arena = Arena::new();
     let c = arena.gc(Object::Simple);
-    let b = arena.gc(Object::Simple);
-    let a = arena.root(Object::Container(vec![c]));
+    let _b = arena.gc(Object::Simple);
+    let _a = arena.root(Object::Container(vec![c]));
     let col = arena.collect();
     assert_eq!(col.total, 3);
     assert_eq!(col.collected, 1);
@@ -103,7 +144,7 @@ fn calls_drop() {
         }
     }
 
-    let a = arena.gc(Object::RunOnDrop(
+    let _a = arena.gc(Object::RunOnDrop(
             RunOnDrop {
                 func: _increment,
                 data: &mut drop_cnt as *mut _ as *mut (),
@@ -115,6 +156,27 @@ fn calls_drop() {
     assert_eq!(drop_cnt, 1);
 }
 
+#[test]
+fn dropping_arena_drops_objects() {
+    let mut drop_cnt = 0usize;
+
+    fn _increment(i: *mut ()) {
+        unsafe {
+            // SAFETY: see calls_drop.
+            *(i as *mut usize) += 1;
+        }
+    }
+
+    let mut arena = Arena::new();
+    let a = arena.root(Object::RunOnDrop(RunOnDrop {
+        func: _increment,
+        data: &mut drop_cnt as *mut _ as *mut (),
+    }));
+    drop(arena);
+    assert_eq!(drop_cnt, 1);
+    assert!(Gc::try_as_ref(&a).is_none());
+}
+
 #[test]
 fn rooted_borrow_lives() {
     let mut arena = Arena::new();
@@ -157,7 +219,7 @@ fn borrow_dies_after_unroot() {
 #[test]
 fn iterative() {
     let mut arena = Arena::new();
-    for i in 0..5 {
+    for _ in 0..5 {
         let mut a = arena.root(Object::Simple);
         let col = arena.collect();
         assert_eq!(col.total, 1);
@@ -183,7 +245,7 @@ fn no_boxes_in_empty_arena() {
 fn no_boxes_in_reclaimed_arena() {
     let mut arena = Arena::new();
     for count in 1..5 {
-        let refs = std::iter::repeat_with(|| {
+        let _refs = std::iter::repeat_with(|| {
             arena.gc(Object::Simple)
         }).take(count).collect::<Vec<_>>();
         let col = arena.collect();
@@ -198,7 +260,7 @@ fn roots_outlive_refs() {
     let mut arena = Arena::new();
     let mut total = 0usize;
     for count in 1..5 {
-        let refs = std::iter::repeat_with(|| {
+        let _refs = std::iter::repeat_with(|| {
             arena.root(Object::Simple)
         }).take(count).collect::<Vec<_>>();
         total += count;
@@ -262,6 +324,15 @@ panic_template!(
     { println!("{:?}", Gc::as_mut(&mut a)); }
 );
 
+#[test]
+#[should_panic(expected = "on collected object of type tracing_gc::test::Object")]
+fn panic_on_collected_names_type() {
+    let mut arena = Arena::new();
+    let a = arena.gc(Object::Simple);
+    arena.collect();
+    Gc::as_ref(&a);
+}
+
 #[test]
 fn later_make_root_saves() {
     let mut arena = Arena::new();
@@ -289,11 +360,21 @@ fn unroot_undoes_make_root() {
     assert_eq!(Gc::try_as_ref(&a), None);
 }
 
+#[test]
+fn unroot_collected_is_harmless() {
+    let mut arena = Arena::new();
+    let a = arena.gc(Object::Simple);
+    arena.collect();
+    arena.unroot(&a);
+    let col = arena.collect();
+    assert_eq!(col.total, 0);
+}
+
 #[test]
 fn multiple_make_roots_are_idempotent() {
     let mut arena = Arena::new();
     let a = arena.gc(Object::Simple);
-    for i in 0..5 {
+    for _ in 0..5 {
         arena.make_root(&a);
     }
     let col = arena.collect();
@@ -316,3 +397,2368 @@ fn same_alloc() {
     assert!(!Gc::ptr_eq(&a, &c));
     assert!(!Gc::ptr_eq(&b, &c));
 }
+
+#[test]
+fn same_alloc_after_collection() {
+    let mut arena = Arena::new();
+    let a = arena.gc(Object::Simple);
+    let b = a.clone();
+    let c = arena.gc(Object::Simple);
+    arena.collect();
+    assert!(Gc::try_as_ref(&a).is_none());
+    assert!(Gc::ptr_eq(&a, &b));
+    assert!(!Gc::ptr_eq(&a, &c));
+}
+
+#[test]
+fn weak_key_resolves_while_alive() {
+    let mut arena = Arena::new();
+    let a = arena.root(Object::Simple);
+    let key = Gc::weak_key(&a);
+    assert_eq!(key, Gc::weak_key(&a.clone()));
+    assert!(arena.resolve(key).is_some());
+    let mut cache = std::collections::HashMap::new();
+    cache.insert(key, "a");
+    assert_eq!(cache.get(&Gc::weak_key(&a)), Some(&"a"));
+}
+
+#[test]
+fn weak_key_dies_with_object() {
+    let mut arena = Arena::new();
+    let a = arena.gc(Object::Simple);
+    let key = Gc::weak_key(&a);
+    let mut cache = std::collections::HashMap::new();
+    cache.insert(key, "a");
+    assert!(arena.resolve(key).is_some());
+    arena.collect();
+    assert_eq!(arena.resolve(key), None);
+    // Even if the allocator hands the same address back, the generation won't match.
+    let b = arena.root(Object::Simple);
+    assert_eq!(arena.resolve(key), None);
+    assert_ne!(Gc::weak_key(&b), key);
+}
+
+#[test]
+fn handle_resolves_while_alive() {
+    let mut arena = Arena::new();
+    let _a = arena.root(Object::Simple);
+    let ptr = arena.iter().next().unwrap();
+    let handle = arena.handle(ptr).unwrap();
+    arena.collect();
+    let resolved = handle.resolve(&arena).unwrap();
+    assert!(ptr::eq(resolved.as_ptr() as *const (), ptr.as_ptr() as *const ()));
+}
+
+#[test]
+fn stale_handle_fails_to_resolve() {
+    let mut arena = Arena::new();
+    let _a = arena.gc(Object::Simple);
+    let ptr = arena.iter().next().unwrap();
+    let handle = arena.handle(ptr).unwrap();
+    arena.collect();
+    assert!(handle.resolve(&arena).is_none());
+    // The pool hands the freed box straight back to a same-sized allocation: the ABA case.
+    let _b = arena.root(Object::Simple);
+    let reused = arena.iter().next().unwrap();
+    assert_eq!(reused.as_ptr() as *const (), ptr.as_ptr() as *const ());
+    assert!(arena.handle(reused).unwrap().resolve(&arena).is_some());
+    assert!(handle.resolve(&arena).is_none());
+}
+
+#[test]
+fn pinned_survives_until_unpinned() {
+    let mut arena = Arena::new();
+    let a = arena.gc(Object::Simple);
+    let ptr = arena.iter().next().unwrap();
+    arena.pin_temporarily(&[ptr]);
+    for _ in 0..2 {
+        let col = arena.collect();
+        assert_eq!(col.total, 1);
+        assert_eq!(col.collected, 0);
+        assert_eq!(&*a, &Object::Simple);
+    }
+    arena.unpin_temporarily();
+    let col = arena.collect();
+    assert_eq!(col.total, 1);
+    assert_eq!(col.collected, 1);
+    assert_eq!(Gc::try_as_ref(&a), None);
+}
+
+struct RawHolder(GcPtrNonNull);
+
+impl Trace for RawHolder {
+    fn trace(&self, visitor: &Visitor) {
+        unsafe {
+            // SAFETY: the tests below only store pointers fresh from the arena's iterator.
+            visitor.visit_ptr(self.0);
+        }
+    }
+}
+
+#[test]
+fn visit_ptr_marks_raw_children() {
+    let mut arena = Arena::new();
+    let child = arena.gc(Object::Container(Vec::new()));
+    let ptr = arena.iter().next().unwrap();
+    let grandchild = arena.gc(Object::Simple);
+    if let Object::Container(v) = &mut *child.clone() {
+        v.push(grandchild.clone());
+    }
+    let garbage = arena.gc(Object::Simple);
+    let _holder = arena.root(RawHolder(ptr));
+    let col = arena.collect();
+    assert_eq!(col.total, 4);
+    assert_eq!(col.collected, 1);
+    assert!(Gc::try_as_ref(&child).is_some());
+    assert!(Gc::try_as_ref(&grandchild).is_some());
+    assert_eq!(Gc::try_as_ref(&garbage), None);
+}
+
+#[test]
+fn replace_returns_old_value() {
+    let mut arena = Arena::new();
+    let mut a = arena.root(Object::Simple);
+    let b = a.clone();
+    let old = Gc::replace(&mut a, Object::Container(Vec::new())).unwrap();
+    assert_eq!(old, Object::Simple);
+    assert!(matches!(&*b, Object::Container(v) if v.is_empty()));
+}
+
+#[test]
+fn replace_on_collected_fails() {
+    let mut arena = Arena::new();
+    let mut a = arena.gc(Object::Simple);
+    arena.collect();
+    assert_eq!(Gc::replace(&mut a, Object::Simple), Err(ReplaceError::Collected));
+}
+
+#[test]
+fn replace_on_borrowed_fails() {
+    let mut arena = Arena::new();
+    let mut a = arena.root(Object::Simple);
+    let b = a.clone();
+    let held = Gc::borrow(&b).unwrap();
+    assert_eq!(Gc::replace(&mut a, Object::Container(Vec::new())), Err(ReplaceError::Borrowed));
+    assert_eq!(*held, Object::Simple);
+    drop(held);
+    assert_eq!(Gc::replace(&mut a, Object::Container(Vec::new())), Ok(Object::Simple));
+}
+
+#[test]
+fn arena_from_iter_roots_everything() {
+    let mut arena: Arena = std::iter::repeat_with(|| Object::Simple).take(5).collect();
+    assert_eq!(arena.len(), 5);
+    assert_eq!(arena.roots_len(), 5);
+    let col = arena.collect();
+    assert_eq!(col.total, 5);
+    assert_eq!(col.collected, 0);
+}
+
+#[test]
+fn slice_views_part_of_gc_slice() {
+    let mut arena = Arena::new();
+    let items = std::iter::repeat_with(|| arena.gc(Object::Simple)).take(4).collect::<Vec<_>>();
+    let garbage = arena.gc(Object::Simple);
+    let whole = arena.gc_slice(items.iter().cloned());
+    arena.make_root(&whole);
+    let part = Gc::slice(&whole, 1..3);
+    assert_eq!(part.len(), 2);
+    assert!(Gc::ptr_eq(&part[0], &items[1]));
+    assert!(Gc::ptr_eq(&part[1], &items[2]));
+    let col = arena.collect();
+    assert_eq!(col.total, 6);
+    assert_eq!(col.collected, 1);
+    assert!(items.iter().all(|item| Gc::try_as_ref(item).is_some()));
+    assert_eq!(Gc::try_as_ref(&garbage), None);
+}
+
+#[test]
+fn try_slice_checks_bounds_and_liveness() {
+    let mut arena = Arena::new();
+    let whole = arena.gc_slice(vec![Object::Simple, Object::Simple]);
+    assert_eq!(Gc::try_slice(&whole, 0..2).map(|s| s.len()), Some(2));
+    assert!(Gc::try_slice(&whole, 1..3).is_none());
+    arena.collect();
+    assert!(Gc::try_slice(&whole, 0..1).is_none());
+}
+
+#[test]
+fn collected_slice_drops_elements() {
+    let mut arena = Arena::new();
+    let mut drop_cnt = 0usize;
+
+    fn _increment(i: *mut ()) {
+        unsafe {
+            // SAFETY: see calls_drop.
+            *(i as *mut usize) += 1;
+        }
+    }
+
+    let _whole = arena.gc_slice(std::iter::repeat_with(|| {
+        Object::RunOnDrop(RunOnDrop {
+            func: _increment,
+            data: &mut drop_cnt as *mut _ as *mut (),
+        })
+    }).take(3));
+    let col = arena.collect();
+    assert_eq!(col.collected, 1);
+    assert_eq!(drop_cnt, 3);
+}
+
+#[test]
+fn finalizer_runs_once_per_freed_object() {
+    let mut arena = Arena::new();
+    let count = Rc::new(Cell::new(0usize));
+    let counter = Rc::clone(&count);
+    arena.register_finalizer(move |obj: &mut Object| {
+        assert_eq!(obj, &Object::Simple);
+        counter.set(counter.get() + 1);
+    });
+    let _garbage = std::iter::repeat_with(|| arena.gc(Object::Simple)).take(3).collect::<Vec<_>>();
+    let _kept = arena.root(Object::Simple);
+    let _other = arena.gc_slice(vec![Object::Simple]);
+    let col = arena.collect();
+    assert_eq!(col.collected, 4);
+    assert_eq!(count.get(), 3);
+    arena.collect();
+    assert_eq!(count.get(), 3);
+}
+
+#[test]
+fn age_histogram_counts_survivals() {
+    let mut arena = Arena::new();
+    let _old = arena.root(Object::Simple);
+    let _garbage = arena.gc(Object::Simple);
+    arena.collect();
+    let middle = arena.root(Object::Simple);
+    let _more_garbage = arena.gc(Object::Simple);
+    arena.collect();
+    let _young = arena.root(Object::Simple);
+    let histogram = arena.age_histogram();
+    assert_eq!(histogram.into_iter().collect::<Vec<_>>(), vec![(0, 1), (1, 1), (2, 1)]);
+    arena.unroot(&middle);
+    arena.collect();
+    let histogram = arena.age_histogram();
+    assert_eq!(histogram.into_iter().collect::<Vec<_>>(), vec![(1, 1), (3, 1)]);
+}
+
+#[test]
+fn cancelled_collection_frees_nothing() {
+    use std::sync::atomic::AtomicBool;
+
+    let mut arena = Arena::new();
+    let child = arena.gc(Object::Simple);
+    let _a = arena.root(Object::Container(vec![child.clone()]));
+    let garbage = arena.gc(Object::Simple);
+    let cancel = AtomicBool::new(true);
+    assert!(matches!(arena.collect_cancellable(&cancel), CollectResult::Cancelled));
+    assert_eq!(arena.len(), 3);
+    assert!(Gc::try_as_ref(&garbage).is_some());
+    assert!(arena.iter().all(|t| !unsafe { t.as_ref().marked() }));
+    cancel.store(false, std::sync::atomic::Ordering::Relaxed);
+    match arena.collect_cancellable(&cancel) {
+        CollectResult::Completed(col) => {
+            assert_eq!(col.total, 3);
+            assert_eq!(col.collected, 1);
+        },
+        CollectResult::Cancelled => panic!("collection cancelled"),
+    }
+    assert!(Gc::try_as_ref(&child).is_some());
+    assert_eq!(Gc::try_as_ref(&garbage), None);
+}
+
+#[test]
+fn cancel_once_sweeping_is_too_late() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    fn cancel(flag: *mut ()) {
+        unsafe { &*(flag as *const AtomicBool) }.store(true, Ordering::Relaxed);
+    }
+
+    let mut arena = Arena::new();
+    let flag = AtomicBool::new(false);
+    let _kept = arena.root(Object::Simple);
+    arena.gc(Object::Simple);
+    arena.gc(Object::Simple);
+    // Newest first, so this is swept before the rest.
+    arena.gc(Object::RunOnDrop(RunOnDrop { func: cancel, data: &flag as *const _ as *mut () }));
+    match arena.collect_cancellable(&flag) {
+        CollectResult::Completed(col) => assert_eq!(col.collected, 3),
+        CollectResult::Cancelled => panic!("collection cancelled"),
+    }
+    assert!(flag.load(Ordering::Relaxed));
+    assert_eq!(arena.len(), 1);
+    assert!(arena.iter().all(|t| !unsafe { t.as_ref().marked() }));
+    // And it counted as a collection.
+    let young = arena.gc(Object::Simple);
+    assert_eq!(Gc::inspect(&young).unwrap().epoch, 1);
+}
+
+#[test]
+fn gc_orders_by_identity() {
+    use std::collections::BTreeSet;
+
+    let mut arena = Arena::new();
+    let a = arena.root(Object::Simple);
+    let b = arena.root(Object::Simple);
+    let mut set = BTreeSet::new();
+    set.insert(a.clone());
+    set.insert(a.clone());
+    set.insert(b.clone());
+    set.insert(a.clone());
+    assert_eq!(set.len(), 2);
+    assert!(set.contains(&a));
+    assert!(set.contains(&b));
+    assert_ne!(a.cmp(&b), CmpOrdering::Equal);
+    assert_eq!(a.cmp(&b), a.clone().cmp(&b.clone()));
+}
+
+#[test]
+fn weak_list_yields_only_survivors() {
+    let mut arena = Arena::new();
+    let observers = arena.root(WeakList::new());
+    let kept = std::iter::repeat_with(|| arena.root(Object::Simple)).take(2).collect::<Vec<_>>();
+    let dropped = std::iter::repeat_with(|| arena.gc(Object::Simple)).take(3).collect::<Vec<_>>();
+    let mut list = observers.clone();
+    for observer in kept.iter().chain(dropped.iter()) {
+        list.push(observer);
+    }
+    assert_eq!(list.len(), 5);
+    let col = arena.collect();
+    assert_eq!(col.collected, 3);
+    let live = list.iter_live().collect::<Vec<_>>();
+    assert!(live == kept);
+    assert_eq!(list.len(), 2);
+}
+
+#[test]
+fn restore_roots_brings_back_snapshot() {
+    let mut arena = Arena::new();
+    let a = arena.root(Object::Simple);
+    let b = arena.root(Object::Simple);
+    let snap = arena.roots_snapshot();
+    arena.unroot(&a);
+    arena.unroot(&b);
+    assert_eq!(arena.roots_len(), 0);
+    arena.restore_roots(snap);
+    assert_eq!(arena.roots_len(), 2);
+    let col = arena.collect();
+    assert_eq!(col.collected, 0);
+    assert!(Gc::try_as_ref(&a).is_some());
+    assert!(Gc::try_as_ref(&b).is_some());
+}
+
+#[test]
+fn restore_roots_drops_dead_entries() {
+    let mut arena = Arena::new();
+    let a = arena.root(Object::Simple);
+    let b = arena.root(Object::Simple);
+    let snap = arena.roots_snapshot();
+    arena.unroot(&b);
+    arena.collect();
+    arena.restore_roots(snap);
+    assert_eq!(arena.roots_len(), 1);
+    assert_eq!(arena.collect().collected, 0);
+    assert!(Gc::try_as_ref(&a).is_some());
+}
+
+#[test]
+fn try_clone_checks_liveness() {
+    let mut arena = Arena::new();
+    let a = arena.root(Object::Simple);
+    let b = arena.gc(Object::Simple);
+    assert!(Gc::try_clone(&a).is_some_and(|c| Gc::ptr_eq(&a, &c)));
+    arena.collect();
+    assert!(Gc::try_clone(&a).is_some());
+    assert!(Gc::try_clone(&b).is_none());
+}
+
+#[test]
+fn with_scope_frees_scope_garbage() {
+    let mut arena = Arena::new();
+    let old_garbage = arena.gc(Object::Simple);
+    let holder = arena.root(Object::Container(Vec::new()));
+    let (kept, freed) = arena.with_scope(|arena| {
+        let kept = arena.gc(Object::Simple);
+        let mut holder = holder.clone();
+        if let Object::Container(v) = &mut *holder {
+            v.push(kept.clone());
+        }
+        let freed = std::iter::repeat_with(|| arena.gc(Object::Simple))
+            .take(3).collect::<Vec<_>>();
+        (kept, freed)
+    });
+    assert!(Gc::try_as_ref(&kept).is_some());
+    assert!(freed.iter().all(|f| Gc::try_as_ref(f).is_none()));
+    assert!(Gc::try_as_ref(&old_garbage).is_some());
+    assert_eq!(arena.len(), 3);
+}
+
+struct WorkQueue {
+    queue: std::collections::VecDeque<Gc<Object>>,
+    done: std::collections::LinkedList<Gc<Object>>,
+}
+
+impl Trace for WorkQueue {
+    fn trace(&self, visitor: &Visitor) {
+        self.queue.trace(visitor);
+        self.done.trace(visitor);
+    }
+}
+
+#[test]
+fn traces_through_deques_and_lists() {
+    let mut arena = Arena::new();
+    let queued = std::iter::repeat_with(|| arena.gc(Object::Simple)).take(3).collect::<Vec<_>>();
+    let done = arena.gc(Object::Simple);
+    let garbage = arena.gc(Object::Simple);
+    let mut work = arena.root(WorkQueue {
+        queue: queued.iter().cloned().collect(),
+        done: std::collections::LinkedList::new(),
+    });
+    work.done.push_back(done.clone());
+    let col = arena.collect();
+    assert_eq!(col.total, 6);
+    assert_eq!(col.collected, 1);
+    assert_eq!(Gc::try_as_ref(&garbage), None);
+    let popped = work.queue.pop_front().unwrap();
+    drop(popped);
+    let col = arena.collect();
+    assert_eq!(col.collected, 1);
+    assert_eq!(Gc::try_as_ref(&queued[0]), None);
+    assert!(Gc::try_as_ref(&done).is_some());
+}
+
+#[test]
+fn shared_gc_roots_until_last_clone_drops() {
+    let mut arena = Arena::new();
+    let shared = arena.gc_shared(Object::Simple);
+    let handle = shared.borrow().clone();
+    let call_sites = vec![shared.clone(), shared.clone()];
+    drop(shared);
+    assert_eq!(arena.collect().collected, 0);
+    assert_eq!(&**call_sites[0].borrow(), &Object::Simple);
+    drop(call_sites);
+    assert_eq!(arena.collect().collected, 1);
+    assert_eq!(Gc::try_as_ref(&handle), None);
+}
+
+#[test]
+fn shared_gc_set_moves_the_root() {
+    let mut arena = Arena::new();
+    let shared = arena.gc_shared(Object::Simple);
+    let first = shared.borrow().clone();
+    let second = arena.gc(Object::Container(Vec::new()));
+    shared.clone().set(second.clone());
+    assert!(Gc::ptr_eq(&shared.borrow(), &second));
+    assert_eq!(arena.collect().collected, 1);
+    assert_eq!(Gc::try_as_ref(&first), None);
+    assert!(Gc::try_as_ref(&second).is_some());
+}
+
+#[test]
+fn largest_live_sorts_by_size() {
+    let mut arena = Arena::new();
+    let _small = arena.gc(Object::Simple);
+    let big = arena.gc_slice(std::iter::repeat_with(|| Object::Simple).take(10));
+    let _tiny = arena.gc_slice(Vec::<Object>::new());
+    let medium = arena.gc_slice(std::iter::repeat_with(|| Object::Simple).take(5));
+    let largest = arena.largest_live(2);
+    assert_eq!(largest.len(), 2);
+    let same = |ptr: GcPtrNonNull, key| {
+        ptr::eq(ptr.as_ptr() as *const (), arena.resolve(key).unwrap().as_ptr() as *const ())
+    };
+    assert!(same(largest[0].0, Gc::weak_key(&big)));
+    assert!(same(largest[1].0, Gc::weak_key(&medium)));
+    assert!(largest[0].1 > largest[1].1);
+    assert!(largest[1].1 > std::mem::size_of::<GcBox<Object>>());
+    assert_eq!(arena.largest_live(10).len(), 4);
+}
+
+#[test]
+fn optimize_root_order_changes_nothing() {
+    let build = || {
+        let mut arena = Arena::new();
+        let mut handles = Vec::new();
+        for i in 0..1000 {
+            let child = arena.gc(Object::Simple);
+            handles.push(child.clone());
+            if i % 3 == 0 {
+                handles.push(arena.root(Object::Container(vec![child])));
+            } else {
+                handles.push(arena.gc(Object::Container(vec![child])));
+            }
+        }
+        (arena, handles)
+    };
+    let (mut plain, plain_handles) = build();
+    let (mut sorted, sorted_handles) = build();
+    sorted.optimize_root_order();
+    assert_eq!(plain.roots_len(), sorted.roots_len());
+    let before = plain.collect();
+    let after = sorted.collect();
+    assert_eq!(before.total, after.total);
+    assert_eq!(before.collected, after.collected);
+    let live = |handles: &[Gc<Object>]| {
+        handles.iter().map(|h| Gc::try_as_ref(h).is_some()).collect::<Vec<_>>()
+    };
+    assert_eq!(live(&plain_handles), live(&sorted_handles));
+}
+
+#[test]
+fn no_garbage_when_all_reachable() {
+    let mut arena = Arena::new();
+    let child = arena.gc(Object::Simple);
+    let _a = arena.root(Object::Container(vec![child]));
+    arena.assert_no_garbage();
+    assert_eq!(arena.len(), 2);
+}
+
+#[test]
+#[should_panic(expected = "2 x tracing_gc::test::Object")]
+fn garbage_fails_leak_check() {
+    let mut arena = Arena::new();
+    let _a = arena.root(Object::Simple);
+    let _leaked = std::iter::repeat_with(|| arena.gc(Object::Simple)).take(2).collect::<Vec<_>>();
+    arena.assert_no_garbage();
+}
+
+#[test]
+fn as_any_downcasts() {
+    let mut arena = Arena::new();
+    let a = arena.root(Object::Simple);
+    let b = arena.gc(Object::Simple);
+    let any = Gc::as_any(&a).unwrap();
+    assert_eq!(any.downcast_ref::<Object>(), Some(&Object::Simple));
+    assert!(any.downcast_ref::<RawHolder>().is_none());
+    arena.collect();
+    assert!(Gc::as_any(&b).is_none());
+}
+
+#[test]
+fn drop_guard_runs_on_collection_only() {
+    let mut arena = Arena::new();
+    let runs = Rc::new(Cell::new(0usize));
+    let counter = Rc::clone(&runs);
+    let a = arena.gc_with_drop_guard(Object::Simple, move || counter.set(counter.get() + 1));
+    arena.make_root(&a);
+    drop(a.clone());
+    arena.collect();
+    assert_eq!(runs.get(), 0);
+    arena.unroot(&a);
+    arena.collect();
+    assert_eq!(runs.get(), 1);
+    arena.collect();
+    assert_eq!(runs.get(), 1);
+}
+
+#[test]
+fn presized_worklist_is_reused() {
+    let mut arena = Arena::new();
+    let children: Vec<_> = (0..1000).map(|_| arena.gc(Object::Simple)).collect();
+    let parent = arena.gc(Object::Container(children.clone()));
+    arena.make_root(&parent);
+    arena.set_mark_capacity(children.len());
+    let before = allocations();
+    for _ in 0..3 {
+        arena.collect();
+    }
+    assert_eq!(allocations(), before);
+    assert_eq!(arena.len(), 1001);
+}
+
+#[test]
+fn value_eq_compares_contents() {
+    let mut arena = Arena::new();
+    let a = arena.gc(Object::Simple);
+    let b = arena.gc(Object::Simple);
+    assert!(Gc::value_eq(&a, &b));
+    assert!(!Gc::ptr_eq(&a, &b));
+    arena.collect();
+    assert!(!Gc::value_eq(&a, &b));
+}
+
+#[test]
+fn tree_builder_builds_reachable_tree() {
+    let mut arena = Arena::new();
+    let root = arena.gc_recursive_builder(Object::Container)
+        .begin().leaf(Object::Simple).leaf(Object::Simple).end()
+        .begin().leaf(Object::Simple).end()
+        .finish();
+    assert_eq!(arena.roots_len(), 1);
+    arena.collect();
+    assert_eq!(arena.len(), 6);
+    match &*root {
+        Object::Container(branches) => {
+            assert_eq!(branches.len(), 2);
+            assert!(branches.iter().all(|b| matches!(&**b, Object::Container(leaves) if !leaves.is_empty())));
+        },
+        _ => panic!("root isn't a container"),
+    }
+    arena.unroot(&root);
+    arena.collect();
+    assert!(arena.is_empty());
+}
+
+#[test]
+fn cycles_reclaimed_accumulates() {
+    let mut arena = Arena::new();
+    arena.set_count_cycles(true);
+    let mut handles = Vec::new();
+    for _ in 0..3 {
+        let mut a = arena.gc(Object::Container(Vec::new()));
+        let b = arena.gc(Object::Container(vec![a.clone()]));
+        Gc::replace(&mut a, Object::Container(vec![b.clone()])).unwrap();
+        handles.push((a, b));
+    }
+    let mut selfish = arena.gc(Object::Container(Vec::new()));
+    let cycle = Object::Container(vec![selfish.clone()]);
+    Gc::replace(&mut selfish, cycle).unwrap();
+    // Garbage, but acyclic.
+    let leaf = arena.gc(Object::Simple);
+    let _chain = arena.gc(Object::Container(vec![leaf.clone()]));
+    arena.collect();
+    assert_eq!(arena.cycles_reclaimed(), 4);
+
+    let mut c = arena.gc(Object::Container(Vec::new()));
+    let d = arena.gc(Object::Container(vec![c.clone()]));
+    Gc::replace(&mut c, Object::Container(vec![d.clone()])).unwrap();
+    arena.collect();
+    assert_eq!(arena.cycles_reclaimed(), 5);
+    assert!(arena.is_empty());
+
+    arena.set_count_cycles(false);
+    let mut e = arena.gc(Object::Container(Vec::new()));
+    let cycle = Object::Container(vec![e.clone()]);
+    Gc::replace(&mut e, cycle).unwrap();
+    assert_eq!(arena.collect().collected, 1);
+    assert_eq!(arena.cycles_reclaimed(), 5);
+}
+
+#[test]
+fn pinned_slice_survives_without_roots() {
+    let mut arena = Arena::new();
+    let table = arena.gc_pinned_slice(vec![Object::Simple, Object::Simple]);
+    arena.collect();
+    assert_eq!(arena.roots_len(), 0);
+    assert_eq!(Gc::try_as_ref(&table).map(<[_]>::len), Some(2));
+}
+
+#[derive(Trace)]
+enum Value {
+    Nil,
+    Int(i64),
+    Str(Gc<str>),
+    List(Gc<Vec<Value>>),
+}
+
+#[test]
+fn derived_value_traces_strings_and_lists() {
+    let mut arena = Arena::new();
+    let hello = arena.gc_str("hello");
+    let inner = arena.gc(vec![Value::Int(1), Value::Str(hello.clone()), Value::Nil]);
+    let world = arena.gc_str("world");
+    let outer = arena.gc(vec![Value::List(inner.clone()), Value::Str(world.clone())]);
+    let garbage = arena.gc_str("garbage");
+    arena.make_root(&outer);
+    arena.collect();
+    assert_eq!(arena.len(), 4);
+    assert_eq!(&*hello, "hello");
+    match &outer[1] {
+        Value::Str(s) => assert_eq!(&**s, "world"),
+        _ => panic!("expected a string"),
+    }
+    assert!(Gc::try_as_ref(&garbage).is_none());
+    arena.unroot(&outer);
+    arena.collect();
+    assert!(arena.is_empty());
+    assert!(Gc::try_as_ref(&hello).is_none());
+}
+
+#[cfg(debug_assertions)]
+#[test]
+#[should_panic(expected = "the object list is corrupt")]
+fn sweep_catches_double_free() {
+    // Never dropped: after the panic, its list still leads into freed boxes.
+    let mut arena = mem::ManuallyDrop::new(Arena::new());
+    let older = arena.gc(Object::Simple);
+    let newer = arena.gc(Object::Simple);
+    unsafe {
+        // Point the front box's prev at its successor, so unlinking it makes the successor link
+        // to itself.
+        let older_box = (*older.ptr.get()).inner.unwrap();
+        (*(*newer.ptr.get()).inner.unwrap().as_ptr()).prev = erase(older_box).as_ptr();
+    }
+    arena.collect();
+}
+
+#[test]
+fn recycled_ids_dont_resolve_stale() {
+    let mut arena = Arena::new();
+    let (kept, kept_id) = arena.gc_with_id(Object::Simple);
+    let (_lost, lost_id) = arena.gc_with_id(Object::Simple);
+    arena.make_root(&kept);
+    arena.collect();
+    assert!(arena.get_by_id(lost_id).is_none());
+    let (reuse, reuse_id) = arena.gc_with_id(Object::Simple);
+    assert_eq!(reuse_id.index(), lost_id.index());
+    assert_ne!(reuse_id, lost_id);
+    assert!(arena.get_by_id(lost_id).is_none());
+    let resolved = arena.get_by_id(reuse_id).unwrap();
+    let expected = arena.resolve(Gc::weak_key(&reuse)).unwrap();
+    assert!(ptr::eq(resolved.as_ptr() as *const (), expected.as_ptr() as *const ()));
+    assert!(arena.get_by_id(kept_id).is_some());
+}
+
+#[test]
+fn split_off_garbage_finalizes_on_drop() {
+    let mut arena = Arena::new();
+    let count = Rc::new(Cell::new(0usize));
+    let counter = Rc::clone(&count);
+    arena.register_finalizer(move |_: &mut Object| counter.set(counter.get() + 1));
+    let kept = arena.root(Object::Simple);
+    let a = arena.gc(Object::Simple);
+    let b = arena.gc(Object::Container(vec![a.clone()]));
+    let garbage = arena.split_off_unreachable();
+    assert_eq!(arena.len(), 1);
+    assert_eq!(garbage.len(), 2);
+    assert!(arena.resolve(Gc::weak_key(&a)).is_none());
+    assert!(garbage.resolve(Gc::weak_key(&b)).is_some());
+    assert_eq!(count.get(), 0);
+    drop(garbage);
+    assert_eq!(count.get(), 2);
+    assert!(Gc::try_as_ref(&a).is_none());
+    assert!(Gc::try_as_ref(&kept).is_some());
+}
+
+#[test]
+fn weak_works_as_map_key() {
+    let mut arena = Arena::new();
+    let a = arena.root(Object::Simple);
+    let b = arena.root(Object::Simple);
+    let mut names = HashMap::new();
+    names.insert(Gc::downgrade(&a), "a");
+    names.insert(Gc::downgrade(&b), "b");
+    assert_eq!(names.get(&Gc::downgrade(&a)), Some(&"a"));
+    assert_eq!(names.get(&Gc::downgrade(&b.clone())), Some(&"b"));
+    assert!(Gc::downgrade(&a) == a);
+    assert!(a != Gc::downgrade(&b));
+}
+
+#[test]
+fn stable_walk_diff_finds_new_objects() {
+    let mut arena = Arena::new();
+    let _a = arena.root(Object::Simple);
+    let _b = arena.root(Object::Simple);
+    arena.collect();
+    let before = arena.stable_walk();
+    let s = arena.gc_str("new");
+    arena.make_root(&s);
+    let after = arena.stable_walk();
+    let new: Vec<_> = after.iter().filter(|o| !before.contains(o)).collect();
+    assert_eq!(new.len(), 1);
+    assert_eq!(new[0].type_id, TypeId::of::<str>());
+    assert_eq!(new[0].epoch, 1);
+    assert_eq!(after.last(), Some(new[0]));
+}
+
+#[test]
+fn gc_cow_clones_shared_value_on_write() {
+    let mut arena = Arena::new();
+    let mut a = arena.gc_cow(vec![1, 2, 3]);
+    let b = a.clone();
+    a.make_mut(&mut arena).push(4);
+    assert_eq!(*a, [1, 2, 3, 4]);
+    assert_eq!(*b, [1, 2, 3]);
+    assert!(!Gc::ptr_eq(a.as_gc(), b.as_gc()));
+    // Now unshared, so writes go in place.
+    let unique = Gc::weak_key(a.as_gc());
+    a.make_mut(&mut arena).push(5);
+    assert_eq!(Gc::weak_key(a.as_gc()), unique);
+    assert_eq!(arena.len(), 2);
+}
+
+#[test]
+fn gc_cow_write_keeps_the_root() {
+    let mut arena = Arena::new();
+    let mut a = arena.gc_cow(vec![1, 2, 3]);
+    arena.make_root(a.as_gc());
+    let b = a.clone();
+    a.make_mut(&mut arena).push(4);
+    drop(b);
+    assert_eq!(arena.collect().collected, 1);
+    assert_eq!(*a, [1, 2, 3, 4]);
+    assert_eq!(arena.roots().count(), 1);
+}
+
+#[test]
+fn path_to_follows_references_from_root() {
+    let mut arena = Arena::new();
+    let target = arena.gc(Object::Simple);
+    let b = arena.gc(Object::Container(vec![target.clone()]));
+    let a = arena.gc(Object::Container(vec![b.clone()]));
+    let root = arena.root(Object::Container(vec![a.clone()]));
+    let stray = arena.gc(Object::Simple);
+    let path = arena.path_to(&target).unwrap();
+    let expected: Vec<_> = [&root, &a, &b, &target].iter()
+        .map(|gc| arena.resolve(Gc::weak_key(gc)).unwrap().as_ptr() as *const ())
+        .collect();
+    assert_eq!(path.iter().map(|p| p.as_ptr() as *const ()).collect::<Vec<_>>(), expected);
+    assert!(arena.path_to(&stray).is_none());
+
+    // Roots still waiting to be registered count too.
+    arena.set_deferred_rooting(true);
+    let pending = arena.root(Object::Container(vec![stray.clone()]));
+    let path = arena.path_to(&stray).unwrap();
+    assert_eq!(path.len(), 2);
+    let pending = arena.resolve(Gc::weak_key(&pending)).unwrap();
+    assert_eq!(path[0].as_ptr() as *const (), pending.as_ptr() as *const ());
+}
+
+#[allow(dead_code)]  // only here to be traced
+#[derive(Trace)]
+struct Tagged<T, const N: usize> where T: Clone {
+    head: Gc<Object>,
+    rest: Vec<Gc<Object>>,
+    tag: T,
+    #[trace(skip)]
+    cached: Gc<Object>,
+    #[trace(skip)]
+    seen: [u8; N],
+}
+
+#[allow(dead_code)]
+#[derive(Trace)]
+enum Either<'a, L, R = Gc<Object>> {
+    Left(L, #[trace(skip)] &'a str),
+    Right { value: R },
+}
+
+#[test]
+fn derive_handles_generics_and_skip() {
+    let mut arena = Arena::new();
+    let head = arena.gc(Object::Simple);
+    let rest = arena.gc(Object::Simple);
+    let tag = arena.gc(Object::Simple);
+    let cached = arena.gc(Object::Simple);
+    let _tagged = arena.root(Tagged {
+        head: head.clone(),
+        rest: vec![rest.clone()],
+        tag: tag.clone(),
+        cached: cached.clone(),
+        seen: [0; 4],
+    });
+    let right = arena.gc(Object::Simple);
+    let _either: Gc<Either<'static, ()>> = arena.root(Either::Right { value: right.clone() });
+    arena.collect();
+    assert!(Gc::try_as_ref(&head).is_some());
+    assert!(Gc::try_as_ref(&rest).is_some());
+    assert!(Gc::try_as_ref(&tag).is_some());
+    assert!(Gc::try_as_ref(&right).is_some());
+    assert!(Gc::try_as_ref(&cached).is_none());
+}
+
+#[derive(Trace)]
+struct Pair {
+    left: Gc<Object>,
+    right: Gc<Object>,
+}
+
+#[test]
+fn boxed_trait_objects_trace_and_collect() {
+    let mut arena = Arena::new();
+    let child = arena.gc(Object::Simple);
+    let left = arena.gc(Object::Simple);
+    let right = arena.gc(Object::Simple);
+    let container: Box<dyn Trace> = Box::new(Object::Container(vec![child.clone()]));
+    let pair: Box<dyn Trace> = Box::new(Pair { left: left.clone(), right: right.clone() });
+    let container = arena.gc_boxed_trait(container);
+    let pair = arena.gc_boxed_trait(pair);
+    arena.make_root(&container);
+    arena.make_root(&pair);
+    arena.collect();
+    assert_eq!(arena.len(), 5);
+    arena.unroot(&pair);
+    arena.collect();
+    assert!(Gc::try_as_ref(&pair).is_none());
+    assert!(Gc::try_as_ref(&left).is_none());
+    assert!(Gc::try_as_ref(&child).is_some());
+    arena.unroot(&container);
+    arena.collect();
+    assert!(arena.is_empty());
+}
+
+#[test]
+fn boxed_trait_objects_move_out_of_their_box() {
+    #[repr(align(64))]
+    struct Aligned(Object);
+
+    impl Trace for Aligned {
+        fn trace(&self, visitor: &Visitor) {
+            self.0.trace(visitor);
+        }
+    }
+
+    fn count(drops: *mut ()) {
+        unsafe { *(drops as *mut usize) += 1 };
+    }
+
+    let mut arena = Arena::new();
+    let mut drops = 0usize;
+    let on_drop = RunOnDrop { func: count, data: &mut drops as *mut usize as *mut () };
+    let value: Box<dyn Trace> = Box::new(Aligned(Object::RunOnDrop(on_drop)));
+    // So the arena's bookkeeping for the type is already there.
+    arena.gc_boxed_trait(Box::new(Object::Simple));
+    let live = live_allocations();
+    let gc = arena.gc_boxed_trait(value);
+    // The box and the Gc's Rc, for the Box given up.
+    assert_eq!(live_allocations(), live + 1);
+    assert_eq!(Gc::as_ptr(&gc) as *const u8 as usize % 64, 0);
+    assert_eq!(Gc::inspect(&gc).unwrap().type_name, std::any::type_name::<dyn Trace>());
+    assert_eq!(arena.count_of_type::<dyn Trace>(), 2);
+    arena.collect();
+    assert_eq!(drops, 1);
+}
+
+#[test]
+fn weak_upgrades_until_swept() {
+    let mut arena = Arena::new();
+    let a = arena.root(Object::Simple);
+    let weak = Gc::downgrade(&a);
+    drop(a);
+    arena.collect();
+    let a = weak.upgrade().expect("rooted object was collected");
+    assert_eq!(&*a, &Object::Simple);
+    arena.unroot(&a);
+    drop(a);
+    arena.collect();
+    assert!(weak.upgrade().is_none());
+}
+
+#[test]
+fn dead_weaks_are_counted_then_released() {
+    fn churn(arena: &mut Arena) {
+        let gc = arena.gc(Object::Simple);
+        let weak = Gc::downgrade(&gc);
+        drop(gc);
+        // Still in the heap, so the Weak works without any Gc around.
+        assert!(weak.upgrade().is_some());
+        arena.collect();
+        assert_eq!(arena.dead_weak_count(), 1);
+        assert!(weak.upgrade().is_none());
+        drop(weak);
+        assert_eq!(arena.dead_weak_count(), 0);
+        arena.collect();
+    }
+
+    let mut arena = Arena::new();
+    // Once first, so the arena's own buffers are already grown.
+    churn(&mut arena);
+    let before = live_allocations();
+    churn(&mut arena);
+    assert_eq!(live_allocations(), before);
+}
+
+#[test]
+fn on_unreachable_sees_each_freed_object() {
+    let mut arena = Arena::new();
+    let freed = Rc::new(RefCell::new(Vec::new()));
+    let record = Rc::clone(&freed);
+    arena.on_unreachable(move |ptr| record.borrow_mut().push(ptr.as_ptr() as *const () as usize));
+    let _kept = arena.root(Object::Simple);
+    let garbage: Vec<_> = (0..3).map(|_| arena.gc(Object::Simple)).collect();
+    let mut expected: Vec<_> = garbage.iter()
+        .map(|gc| arena.resolve(Gc::weak_key(gc)).unwrap().as_ptr() as *const () as usize)
+        .collect();
+    arena.collect();
+    let mut freed = freed.borrow().clone();
+    freed.sort_unstable();
+    expected.sort_unstable();
+    assert_eq!(freed, expected);
+}
+
+#[test]
+fn borrow_two_mut_swaps_distinct_objects() {
+    let mut arena = Arena::new();
+    let mut a = arena.root(vec![1]);
+    let mut b = arena.root(vec![2, 3]);
+    let (x, y) = Gc::borrow_two_mut(&mut a, &mut b).unwrap();
+    mem::swap(x, y);
+    assert_eq!(*a, [2, 3]);
+    assert_eq!(*b, [1]);
+}
+
+#[test]
+fn borrow_two_mut_refuses_same_object() {
+    let mut arena = Arena::new();
+    let mut a = arena.root(Object::Simple);
+    let mut also_a = a.clone();
+    assert_eq!(Gc::borrow_two_mut(&mut a, &mut also_a).err(), Some(BorrowError::SameObject));
+}
+
+#[test]
+fn borrow_two_mut_refuses_collected() {
+    let mut arena = Arena::new();
+    let mut a = arena.root(Object::Simple);
+    let mut b = arena.gc(Object::Simple);
+    arena.collect();
+    assert_eq!(Gc::borrow_two_mut(&mut a, &mut b).err(), Some(BorrowError::Collected));
+}
+
+#[test]
+fn vec_with_capacity_doesnt_reallocate() {
+    let mut arena = Arena::new();
+    let mut list = arena.gc_vec_with_capacity(100);
+    let buffer = list.as_ptr();
+    for _ in 0..100 {
+        let item = arena.gc(Object::Simple);
+        list.push(item);
+    }
+    assert_eq!(list.as_ptr(), buffer);
+}
+
+#[test]
+fn batch_threads_in_order() {
+    let mut arena = Arena::new();
+    let older = arena.gc(Object::Simple);
+    let batch = arena.gc_batch((0..1000).map(|_| Object::Simple).collect());
+    let expected: Vec<_> = batch.iter().chain(Some(&older))
+        .map(|gc| arena.resolve(Gc::weak_key(gc)).unwrap().as_ptr() as *const ())
+        .collect();
+    let listed: Vec<_> = arena.iter().map(|p| p.as_ptr() as *const ()).collect();
+    assert_eq!(listed, expected);
+    for gc in batch.iter().step_by(2) {
+        arena.make_root(gc);
+    }
+    arena.collect();
+    assert_eq!(arena.len(), 500);
+    assert!(batch.iter().skip(1).step_by(2).all(|gc| Gc::try_as_ref(gc).is_none()));
+}
+
+#[test]
+fn alloc_hook_sees_every_allocation() {
+    let mut arena = Arena::new();
+    let seen = Rc::new(RefCell::new(Vec::new()));
+    let record = Rc::clone(&seen);
+    arena.set_alloc_hook(move |_, type_id| record.borrow_mut().push(type_id));
+    let _a = arena.gc(Object::Simple);
+    let _b = arena.root(Object::Simple);
+    let _s = arena.gc_str("str");
+    let _batch = arena.gc_batch(vec![1u32, 2]);
+    let seen = seen.borrow();
+    assert_eq!(seen.len(), 5);
+    assert_eq!(seen[..2], [TypeId::of::<Object>(); 2]);
+    assert_eq!(seen[2], TypeId::of::<str>());
+    assert_eq!(seen[3..], [TypeId::of::<u32>(); 2]);
+}
+
+#[test]
+fn timing_reports_both_phases() {
+    let mut arena = Arena::new();
+    arena.set_timing(true);
+    let _kept: Vec<_> = (0..10000).map(|_| arena.root(Object::Simple)).collect();
+    let _garbage: Vec<_> = (0..10000).map(|_| arena.gc(Object::Simple)).collect();
+    // Only that both are there: a coarse clock can report either as zero.
+    assert!(arena.collect().timing.is_some());
+    arena.set_timing(false);
+    assert!(arena.collect().timing.is_none());
+}
+
+#[derive(Trace)]
+#[repr(transparent)]
+struct Env(Gc<Object>);
+
+#[test]
+fn derived_newtype_traces_inner_field() {
+    let mut arena = Arena::new();
+    let scope = arena.gc(Object::Simple);
+    let env = arena.gc(Env(scope.clone()));
+    arena.make_root(&env);
+    arena.collect();
+    assert_eq!(arena.len(), 2);
+    assert!(Gc::try_as_ref(&scope).is_some());
+    arena.unroot(&env);
+    arena.collect();
+    assert!(arena.is_empty());
+}
+
+#[derive(Default)]
+struct Counts {
+    allocs: usize,
+    frees: usize,
+    collections: usize,
+    collected: usize,
+}
+
+struct RecordingSink(Rc<RefCell<Counts>>);
+
+impl MetricsSink for RecordingSink {
+    fn on_alloc(&mut self, _type_id: TypeId) {
+        self.0.borrow_mut().allocs += 1;
+    }
+
+    fn on_free(&mut self, _type_id: TypeId) {
+        self.0.borrow_mut().frees += 1;
+    }
+
+    fn on_collection(&mut self, col: &Collection) {
+        let mut counts = self.0.borrow_mut();
+        counts.collections += 1;
+        counts.collected += col.collected;
+    }
+}
+
+#[test]
+fn metrics_sink_sees_allocs_frees_and_collections() {
+    let counts = Rc::new(RefCell::new(Counts::default()));
+    let mut arena = Arena::new();
+    arena.set_metrics_sink(RecordingSink(counts.clone()));
+    let _kept = arena.root(Object::Simple);
+    for _ in 0..3 {
+        arena.gc(Object::Simple);
+    }
+    arena.gc_str("also garbage");
+    let name = arena.gc_str("kept");
+    arena.make_root(&name);
+    assert_eq!(counts.borrow().allocs, 6);
+    arena.collect();
+    let counts = counts.borrow();
+    assert_eq!(counts.frees, 4);
+    assert_eq!(counts.collections, 1);
+    assert_eq!(counts.collected, 4);
+}
+
+#[test]
+fn detached_objects_survive_until_adopted() {
+    let mut arena = Arena::new();
+    let loner = arena.root(Object::Simple);
+    let other = arena.gc(Object::Simple);
+    assert!(arena.detach(&loner));
+    assert_eq!(arena.roots_len(), 0);
+    arena.collect();
+    assert!(arena.is_empty());
+    assert!(Gc::try_as_ref(&loner).is_some());
+    assert!(Gc::try_as_ref(&other).is_none());
+    assert!(!arena.detach(&other));
+
+    assert!(arena.adopt(&loner));
+    assert_eq!(arena.len(), 1);
+    arena.collect();
+    assert!(arena.is_empty());
+    assert!(Gc::try_as_ref(&loner).is_none());
+    assert!(!arena.adopt(&loner));
+}
+
+#[test]
+fn detach_takes_shared_roots_too() {
+    let mut scratch = Arena::new();
+    let mut main = Arena::new();
+    let (guarded, guard) = scratch.root_guard(Object::Simple);
+    assert!(scratch.detach(&guarded));
+    // Out of scratch's shared roots as well, so it's fine for main to free it.
+    assert!(main.adopt(&guarded));
+    assert_eq!(main.collect().collected, 1);
+    assert_eq!(scratch.collect().total, 0);
+    drop(guard);
+}
+
+#[test]
+fn interned_strings_are_shared_until_collected() {
+    let mut arena = Arena::new();
+    let a = arena.intern("hello");
+    let b = arena.intern("hello");
+    assert!(Gc::ptr_eq(&a, &b));
+    assert!(!Gc::ptr_eq(&a, &arena.intern("world")));
+    assert_eq!(arena.len(), 2);
+
+    arena.collect();
+    assert!(Gc::try_as_ref(&a).is_none());
+    let c = arena.intern("hello");
+    assert_eq!(&*c, "hello");
+    assert_eq!(arena.len(), 1);
+    arena.make_root(&c);
+    arena.collect();
+    assert!(Gc::ptr_eq(&c, &arena.intern("hello")));
+}
+
+#[test]
+fn allocations_count_down_to_collection() {
+    let mut arena = Arena::new();
+    assert_eq!(arena.allocs_until_collection(), None);
+    assert_eq!(arena.bytes_until_collection(), None);
+    arena.set_auto_collect_threshold(Some(10));
+    arena.set_auto_collect_bytes(Some(100 * mem::size_of::<GcBox<Object>>()));
+    for _ in 0..3 {
+        arena.gc(Object::Simple);
+    }
+    assert_eq!(arena.allocs_until_collection(), Some(7));
+    assert_eq!(arena.bytes_until_collection(), Some(97 * mem::size_of::<GcBox<Object>>()));
+    arena.collect();
+    assert_eq!(arena.allocs_until_collection(), Some(10));
+}
+
+#[test]
+fn collections_report_bytes() {
+    let mut arena = Arena::new();
+    let _kept = arena.root(Object::Simple);
+    for _ in 0..5 {
+        arena.gc(Object::Simple);
+    }
+    let col = arena.collect();
+    assert_eq!(col.bytes_collected, 5 * mem::size_of::<GcBox<Object>>());
+    assert_eq!(col.bytes_live, mem::size_of::<GcBox<Object>>());
+}
+
+#[test]
+fn root_guard_unroots_when_dropped() {
+    let mut arena = Arena::new();
+    let outer = {
+        let (gc, _guard) = arena.root_guard(Object::Simple);
+        arena.collect();
+        assert!(Gc::try_as_ref(&gc).is_some());
+        gc
+    };
+    arena.collect();
+    assert!(Gc::try_as_ref(&outer).is_none());
+}
+
+thread_local! {
+    static REENTRANT_TOTAL: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+#[test]
+fn reentrant_collect_does_nothing() {
+    fn collect_again(arena: *mut ()) {
+        let col = unsafe { (*(arena as *mut Arena)).collect() };
+        REENTRANT_TOTAL.with(|total| total.set(Some(col.total + col.collected)));
+    }
+
+    let mut arena = Arena::new();
+    let arena_ptr = &mut arena as *mut Arena as *mut ();
+    arena.gc(Object::RunOnDrop(RunOnDrop { func: collect_again, data: arena_ptr }));
+    arena.gc(Object::Simple);
+    let col = arena.collect();
+    assert_eq!(col.collected, 2);
+    assert_eq!(REENTRANT_TOTAL.with(Cell::get), Some(0));
+    assert!(arena.is_empty());
+    // And the guard's been let go of.
+    arena.gc(Object::Simple);
+    assert_eq!(arena.collect().collected, 1);
+}
+
+trait Shape: Trace {
+    fn area(&self) -> u32;
+}
+
+#[derive(Trace)]
+struct Square(u32);
+
+#[derive(Trace)]
+struct Group(Vec<Gc<dyn Shape>>, #[trace(skip)] u32);
+
+impl Shape for Square {
+    fn area(&self) -> u32 {
+        self.0 * self.0
+    }
+}
+
+impl Shape for Group {
+    fn area(&self) -> u32 {
+        self.0.iter().map(|shape| shape.area()).sum::<u32>() + self.1
+    }
+}
+
+#[test]
+fn gc_dyn_holds_different_types() {
+    let mut arena = Arena::new();
+    let square: Gc<dyn Shape> = gc_dyn!(&mut arena, Square(3));
+    let lost: Gc<dyn Shape> = gc_dyn!(&mut arena, Square(100));
+    let group: Gc<dyn Shape> = gc_dyn!(&mut arena, Group(vec![square.clone()], 1));
+    arena.make_root(&group);
+    let col = arena.collect();
+    assert_eq!(col.collected, 1);
+    assert!(Gc::try_as_ref(&lost).is_none());
+    assert_eq!(group.area(), 10);
+    assert_eq!(Gc::inspect(&square).unwrap().type_id, TypeId::of::<Square>());
+    arena.unroot(&group);
+    arena.collect();
+    assert!(arena.is_empty());
+}
+
+#[test]
+fn on_finalize_runs_each_once_on_collection() {
+    let mut arena = Arena::new();
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let a = arena.root(Object::Simple);
+    for i in 0..3 {
+        let log = Rc::clone(&log);
+        assert!(arena.on_finalize(&a, Box::new(move || log.borrow_mut().push(i))));
+    }
+    arena.collect();
+    assert!(log.borrow().is_empty());
+    arena.unroot(&a);
+    arena.collect();
+    arena.collect();
+    assert_eq!(*log.borrow(), vec![0, 1, 2]);
+    assert!(!arena.on_finalize(&a, Box::new(|| panic!("finalized a collected object"))));
+}
+
+#[test]
+fn drop_guards_move_with_their_objects() {
+    let runs = Rc::new(Cell::new(0usize));
+    let guard = || {
+        let counter = Rc::clone(&runs);
+        move || counter.set(counter.get() + 1)
+    };
+    let mut source = Arena::new();
+    let mut dest = Arena::new();
+    let a = source.gc_with_drop_guard(Object::Simple, guard());
+    source.make_root(&a);
+    assert!(dest.adopt_from(&mut source, &a));
+    dest.unroot(&a);
+    source.collect();
+    assert_eq!(runs.get(), 0);
+    dest.collect();
+    assert_eq!(runs.get(), 1);
+
+    let _b = source.gc_with_drop_guard(Object::Simple, guard());
+    let split = source.split_off_unreachable();
+    source.collect();
+    assert_eq!(runs.get(), 1);
+    drop(split);
+    assert_eq!(runs.get(), 2);
+
+    // Not this arena's to finalize.
+    let c = dest.gc(Object::Simple);
+    assert!(!source.on_finalize(&c, Box::new(guard())));
+}
+
+#[test]
+fn try_unwrap_moves_out_unique_objects() {
+    fn increment(i: *mut ()) {
+        unsafe {
+            // SAFETY: Points to drops below, which outlives the value.
+            *(i as *mut usize) += 1;
+        }
+    }
+
+    let mut drops = 0usize;
+    let mut arena = Arena::new();
+    let a = arena.root(Object::Simple);
+    let weak = Gc::downgrade(&a);
+    assert_eq!(Gc::try_unwrap(a, &mut arena).ok(), Some(Object::Simple));
+    assert!(weak.upgrade().is_none());
+    assert!(arena.is_empty());
+    assert_eq!(arena.roots_len(), 0);
+
+    let b = arena.gc_with_layout(Object::RunOnDrop(RunOnDrop {
+        func: increment,
+        data: &mut drops as *mut _ as *mut (),
+    }), Layout::from_size_align(256, 64).unwrap());
+    let value = Gc::try_unwrap(b, &mut arena).ok().unwrap();
+    assert_eq!(drops, 0);
+    drop(value);
+    assert_eq!(drops, 1);
+    assert_eq!(arena.collect().total, 0);
+}
+
+#[test]
+fn try_unwrap_gives_back_shared_or_collected() {
+    let mut arena = Arena::new();
+    let a = arena.root(Object::Simple);
+    let b = a.clone();
+    let a = Gc::try_unwrap(a, &mut arena).unwrap_err();
+    assert!(Gc::ptr_eq(&a, &b));
+    assert_eq!(arena.len(), 1);
+    arena.unroot(&a);
+    drop(b);
+    arena.collect();
+    assert!(Gc::try_unwrap(a, &mut arena).is_err());
+}
+
+#[test]
+fn roots_iterates_the_roots() {
+    let same = |gc: &Gc<Object>, ptr: GcPtrNonNull| {
+        Gc::inspect(gc).unwrap().generation == unsafe { ptr.as_ref().generation() }
+    };
+    let mut arena = Arena::new();
+    let a = arena.root(Object::Simple);
+    let b = arena.root(Object::Simple);
+    let c = arena.root(Object::Simple);
+    arena.gc(Object::Simple);
+    arena.unroot(&b);
+    let roots: Vec<_> = arena.roots().collect();
+    assert_eq!(roots.len(), 2);
+    assert!(roots.iter().any(|&r| same(&a, r)));
+    assert!(roots.iter().any(|&r| same(&c, r)));
+
+    arena.set_deferred_rooting(true);
+    let d = arena.root(Object::Simple);
+    assert_eq!(arena.roots().count(), 3);
+    assert!(arena.roots().any(|r| same(&d, r)));
+}
+
+#[test]
+fn gc_cell_mutates_through_clones_and_traces() {
+    let mut arena = Arena::new();
+    let cell = arena.root(GcCell::new(Object::Container(Vec::new())));
+    let other = cell.clone();
+    let child = arena.gc(Object::Simple);
+    if let Object::Container(children) = &mut *other.borrow_mut() {
+        children.push(child.clone());
+    }
+    arena.collect();
+    assert!(Gc::try_as_ref(&child).is_some());
+    assert!(matches!(&*cell.borrow(), Object::Container(v) if v.len() == 1));
+    *cell.borrow_mut() = Object::Simple;
+    arena.collect();
+    assert!(Gc::try_as_ref(&child).is_none());
+}
+
+#[test]
+fn gc_cell_borrowed_through_a_collection() {
+    let mut arena = Arena::new();
+    let cell = arena.root(GcCell::new(Object::Container(Vec::new())));
+    let child = arena.gc(Object::Simple);
+    {
+        let mut writing = cell.borrow_mut();
+        if let Object::Container(children) = &mut *writing {
+            children.push(child.clone());
+        }
+        // Nothing in the cell can be traced while it's borrowed, so the child goes.
+        arena.collect();
+    }
+    assert!(Gc::try_as_ref(&child).is_none());
+    // And its dead handle, left in the cell, is passed over from then on.
+    assert_eq!(arena.collect().collected, 0);
+    assert_eq!(arena.to_graph().edges.len(), 0);
+    assert!(arena.path_to(&cell).is_some());
+}
+
+#[test]
+#[should_panic]
+fn gc_cell_conflicting_borrows_panic() {
+    let mut arena = Arena::new();
+    let cell = arena.root(GcCell::new(Object::Simple));
+    let other = cell.clone();
+    let _reading = cell.borrow();
+    other.borrow_mut();
+}
+
+#[test]
+fn collect_debug_reports_freed_boxes() {
+    let mut arena = Arena::new();
+    arena.gc(Object::Simple);
+    arena.gc(Object::Simple);
+    let garbage: Vec<_> = arena.iter().map(|t| t.as_ptr() as *const ()).collect();
+    let _kept = arena.root(Object::Simple);
+    let (col, mut freed) = arena.collect_debug();
+    assert_eq!(col.collected, 2);
+    freed.sort();
+    let mut expected = garbage;
+    expected.sort();
+    assert_eq!(freed, expected);
+    assert!(arena.collect_debug().1.is_empty());
+}
+
+#[test]
+fn clear_frees_roots_and_all() {
+    let mut arena = Arena::new();
+    let rooted: Vec<_> = (0..3).map(|_| arena.root(Object::Simple)).collect();
+    let child = arena.gc(Object::Simple);
+    let parent = arena.root(Object::Container(vec![child.clone()]));
+    let shared = arena.gc_shared(Object::Simple);
+    arena.clear();
+    assert!(arena.iter().next().is_none());
+    assert_eq!((arena.len(), arena.roots_len()), (0, 0));
+    assert!(rooted.iter().chain([&child, &parent]).all(|gc| Gc::try_as_ref(gc).is_none()));
+    assert!(Gc::try_as_ref(&shared.borrow()).is_none());
+    let again = arena.root(Object::Simple);
+    assert_eq!(arena.collect().total, 1);
+    assert_eq!(&*again, &Object::Simple);
+}
+
+#[test]
+fn arena_debug_summarizes() {
+    let mut arena = Arena::new();
+    let _a = arena.root(Object::Simple);
+    arena.gc(Object::Simple);
+    arena.gc(Object::Simple);
+    assert_eq!(format!("{:?}", arena), "Arena { len: 3, roots: 1, marked: 0 }");
+    arena.mark();
+    assert_eq!(format!("{:?}", arena), "Arena { len: 3, roots: 1, marked: 1 }");
+    arena.sweep();
+}
+
+#[test]
+fn strong_count_counts_handles() {
+    let mut arena = Arena::new();
+    let a = arena.gc(Object::Simple);
+    let mut clones = vec![a.clone(), a.clone(), a.clone()];
+    assert_eq!(Gc::strong_count(&a), 4);
+    let weak = Gc::downgrade(&a);
+    assert_eq!(Gc::weak_count(&a), 1);
+    clones.pop();
+    assert_eq!(Gc::strong_count(&a), 3);
+    drop(clones);
+    assert_eq!(Gc::strong_count(&a), 1);
+    let _b = a.clone();
+    // Swept, but the handles are all still around; only the arena's reference went.
+    arena.collect();
+    assert!(Gc::try_as_ref(&a).is_none());
+    assert_eq!(Gc::strong_count(&a), 2);
+    drop(weak);
+    arena.collect();
+    assert_eq!(Gc::strong_count(&a), 2);
+}
+
+#[test]
+fn adopt_from_moves_between_arenas() {
+    let mut scratch = Arena::new();
+    let mut main = Arena::new();
+    let child = scratch.gc(Object::Simple);
+    let parent = scratch.root(Object::Container(vec![child.clone()]));
+    assert!(main.adopt_from(&mut scratch, &parent));
+    assert!(main.adopt_from(&mut scratch, &child));
+    assert!(!main.adopt_from(&mut scratch, &child));
+    assert_eq!((scratch.len(), scratch.roots_len()), (0, 0));
+    assert_eq!((main.len(), main.roots_len()), (2, 1));
+    assert_eq!(Gc::arena_id(&parent), Some(main.id()));
+    scratch.collect();
+    main.collect();
+    assert!(Gc::try_as_ref(&child).is_some());
+    drop(scratch);
+    assert!(Gc::try_as_ref(&parent).is_some());
+    main.unroot(&parent);
+    assert_eq!(main.collect().collected, 2);
+}
+
+#[test]
+fn adopt_from_leaves_shared_roots_behind() {
+    let mut scratch = Arena::new();
+    let mut main = Arena::new();
+    let (guarded, _guard) = scratch.root_guard(Object::Simple);
+    let shared = scratch.gc_shared(Object::Simple);
+    assert!(!main.adopt_from(&mut scratch, &guarded));
+    assert!(!main.adopt_from(&mut scratch, &*shared.borrow()));
+    assert_eq!(Gc::arena_id(&guarded), Some(scratch.id()));
+    assert_eq!(scratch.collect().collected, 0);
+}
+
+#[test]
+fn freed_boxes_are_pooled_for_reuse() {
+    let mut arena = Arena::new();
+    let _kept = arena.root(Object::Simple);
+    let before = allocations();
+    arena.gc(Object::Simple);
+    // The box and the Gc's Rc.
+    assert_eq!(allocations() - before, 2);
+    for _ in 0..9 {
+        arena.gc(Object::Simple);
+    }
+    arena.collect();
+    assert_eq!(arena.pool_len(), 10);
+    let before = allocations();
+    let reused = arena.root(Object::Simple);
+    assert_eq!(allocations() - before, 1);
+    assert_eq!(arena.pool_len(), 9);
+    // Other layouts don't fit.
+    arena.gc(0u8);
+    assert_eq!(arena.pool_len(), 9);
+    arena.collect();
+    assert_eq!(&*reused, &Object::Simple);
+    assert_eq!(arena.pool_len(), 10);
+}
+
+#[test]
+fn release_pool_frees_pooled_storage() {
+    let mut arena = Arena::new();
+    for _ in 0..5 {
+        arena.gc(Object::Simple);
+    }
+    arena.collect();
+    assert_eq!(arena.pool_len(), 5);
+    let live = live_allocations();
+    arena.release_pool();
+    assert_eq!(arena.pool_len(), 0);
+    // Along with the pool's own lists.
+    assert!(live_allocations() <= live - 5);
+    arena.gc(Object::Simple);
+    assert_eq!(arena.collect().collected, 1);
+}
+
+#[test]
+fn auto_collection_fires_at_threshold() {
+    let mut arena = Arena::new();
+    arena.set_auto_collect_threshold(Some(4));
+    let garbage: Vec<_> = (0..3).map(|_| arena.gc(Object::Simple)).collect();
+    assert_eq!(arena.len(), 3);
+    // The fourth allocation collects, sparing itself though nothing refers to it yet.
+    let fresh = arena.gc(Object::Simple);
+    assert_eq!(arena.len(), 1);
+    assert!(garbage.iter().all(|gc| Gc::try_as_ref(gc).is_none()));
+    assert_eq!(&*fresh, &Object::Simple);
+    assert_eq!(arena.allocs_until_collection(), Some(4));
+
+    arena.set_auto_collect_threshold(None);
+    for _ in 0..10 {
+        arena.gc(Object::Simple);
+    }
+    assert_eq!(arena.len(), 11);
+}
+
+#[test]
+fn inspect_reports_header() {
+    let mut arena = Arena::new();
+    arena.collect();
+    let gc = arena.root(Object::Simple);
+    let other = gc.clone();
+    arena.collect();
+    let info = Gc::inspect(&gc).unwrap();
+    assert_eq!(info.type_id, TypeId::of::<Object>());
+    assert_eq!(info.type_name, std::any::type_name::<Object>());
+    assert_eq!(info.size, mem::size_of::<GcBox<Object>>());
+    assert_eq!(info.epoch, 1);
+    assert_eq!(info.survived_collections, 1);
+    assert!(!info.marked);
+    arena.mark();
+    assert!(Gc::inspect(&gc).unwrap().marked);
+    arena.sweep();
+    assert_eq!(info.strong_count, 2);
+
+    drop(other);
+    arena.unroot(&gc);
+    arena.collect();
+    assert!(Gc::inspect(&gc).is_none());
+}
+
+#[test]
+fn deferred_roots_register_at_collection() {
+    let mut arena = Arena::new();
+    arena.set_deferred_rooting(true);
+    let rooted: Vec<_> = (0..10000).map(|_| arena.root(Object::Simple)).collect();
+    let garbage = arena.gc(Object::Simple);
+    let unrooted = arena.root(Object::Simple);
+    arena.unroot(&unrooted);
+    assert_eq!(arena.roots_len(), 10000);
+    arena.collect();
+    assert_eq!(arena.len(), 10000);
+    assert!(rooted.iter().all(|gc| Gc::try_as_ref(gc).is_some()));
+    assert!(Gc::try_as_ref(&garbage).is_none());
+    assert!(Gc::try_as_ref(&unrooted).is_none());
+    arena.unroot(&rooted[0]);
+    arena.collect();
+    assert_eq!(arena.len(), 9999);
+}
+
+#[test]
+fn count_of_type_matches_walk() {
+    fn walked<T: ?Sized + 'static>(arena: &Arena) -> usize {
+        arena.walk().filter(|t| unsafe { t.cast::<GcBox<()>>().as_ref() }.type_id == TypeId::of::<T>()).count()
+    }
+    fn check(arena: &Arena) {
+        assert_eq!(arena.count_of_type::<Object>(), walked::<Object>(arena));
+        assert_eq!(arena.count_of_type::<str>(), walked::<str>(arena));
+        assert_eq!(arena.count_of_type::<Vec<Value>>(), walked::<Vec<Value>>(arena));
+    }
+    let mut arena = Arena::new();
+    let kept = arena.root(Object::Simple);
+    arena.gc(Object::Simple);
+    let name = arena.gc_str("name");
+    arena.gc_str("garbage");
+    let list = arena.gc(vec![Value::Str(name)]);
+    arena.make_root(&list);
+    check(&arena);
+    assert_eq!(arena.count_of_type::<Object>(), 2);
+    assert_eq!(arena.count_of_type::<str>(), 2);
+    arena.collect();
+    check(&arena);
+    assert_eq!(arena.count_of_type::<Object>(), 1);
+    assert_eq!(arena.count_of_type::<str>(), 1);
+    arena.detach(&kept);
+    check(&arena);
+    arena.adopt(&kept);
+    arena.unroot(&list);
+    let split = arena.split_off_unreachable();
+    check(&arena);
+    check(&split);
+    assert_eq!(split.count_of_type::<Object>(), 1);
+    arena.collect();
+    check(&arena);
+    assert_eq!(arena.count_of_type::<Object>(), 0);
+    assert_eq!(arena.count_of_type::<Vec<Value>>(), 0);
+}
+
+#[test]
+fn sweep_yields_every_chunk() {
+    let yields = Rc::new(Cell::new(0));
+    let mut arena = Arena::new();
+    let counter = yields.clone();
+    arena.set_sweep_yield(100, move || counter.set(counter.get() + 1));
+    let _kept: Vec<_> = (0..450).map(|_| arena.root(Object::Simple)).collect();
+    for _ in 0..600 {
+        arena.gc(Object::Simple);
+    }
+    arena.collect();
+    assert_eq!(yields.get(), 10);
+    arena.collect();
+    assert_eq!(yields.get(), 14);
+}
+
+#[test]
+fn objects_know_their_arena() {
+    let mut first = Arena::new();
+    let mut second = Arena::new();
+    assert_ne!(first.id(), second.id());
+    let a = first.gc(Object::Simple);
+    let b = second.gc_str("b");
+    let c = second.gc_batch(vec![Object::Simple]).pop().unwrap();
+    assert_eq!(Gc::arena_id(&a), Some(first.id()));
+    assert_eq!(Gc::arena_id(&b), Some(second.id()));
+    assert_eq!(Gc::arena_id(&c), Some(second.id()));
+    let split = second.split_off_unreachable();
+    assert_eq!(Gc::arena_id(&b), Some(split.id()));
+    first.collect();
+    assert_eq!(Gc::arena_id(&a), None);
+}
+
+// Stands in for a type from another crate, which couldn't implement Trace here.
+struct Foreign {
+    scope: Gc<Object>,
+    depth: u32,
+}
+
+#[test]
+fn trace_fn_makes_foreign_types_traceable() {
+    let mut arena = Arena::new();
+    let scope = arena.gc(Object::Simple);
+    let foreign = arena.gc_with_trace_fn(Foreign { scope: scope.clone(), depth: 3 }, |f, visitor| {
+        visitor.visit(&f.scope);
+    });
+    arena.make_root(&foreign);
+    arena.collect();
+    assert_eq!(arena.len(), 2);
+    assert_eq!(foreign.depth, 3);
+    assert!(Gc::ptr_eq(&foreign.scope, &scope));
+    assert_eq!(arena.count_of_type::<Foreign>(), 1);
+    arena.unroot(&foreign);
+    arena.collect();
+    assert!(arena.is_empty());
+    assert!(Gc::try_as_ref(&scope).is_none());
+}
+
+#[test]
+fn threshold_collections_report_their_reason() {
+    let mut arena = Arena::new();
+    assert_eq!(arena.last_collect_reason(), None);
+    arena.collect();
+    assert_eq!(arena.last_collect_reason(), Some(CollectReason::Manual));
+    arena.set_auto_collect_threshold(Some(5));
+    for _ in 0..5 {
+        arena.gc(Object::Simple);
+    }
+    assert_eq!(arena.last_collect_reason(), Some(CollectReason::Threshold));
+    arena.set_auto_collect_threshold(None);
+    arena.set_auto_collect_bytes(Some(1));
+    arena.gc(Object::Simple);
+    assert_eq!(arena.last_collect_reason(), Some(CollectReason::ByteThreshold));
+}
+
+#[test]
+fn weak_counts_and_identity() {
+    let mut arena = Arena::new();
+    let a = arena.gc(Object::Simple);
+    let b = arena.gc(Object::Simple);
+    assert_eq!(Gc::weak_count(&a), 0);
+    let first = Gc::downgrade(&a);
+    let second = first.clone();
+    let third = Gc::downgrade(&a);
+    let other = Gc::downgrade(&b);
+    assert_eq!(Gc::weak_count(&a), 3);
+    assert_eq!(Gc::weak_count(&b), 1);
+    assert!(Weak::ptr_eq(&first, &second));
+    assert!(Weak::ptr_eq(&first, &third));
+    assert!(!Weak::ptr_eq(&first, &other));
+    drop(second);
+    drop(third);
+    assert_eq!(Gc::weak_count(&a), 1);
+}
+
+#[test]
+fn gc_list_survives_through_its_head() {
+    let mut arena = Arena::new();
+    let mut list = GcList::new();
+    for i in 0..100 {
+        list.push(&mut arena, i);
+    }
+    assert_eq!(list.len(), 100);
+    let head = list.head().unwrap().clone();
+    arena.make_root(&head);
+    arena.collect();
+    assert_eq!(arena.len(), 100);
+    assert!(list.iter().copied().eq((0..100).rev()));
+    assert_eq!(list.pop(), Some(99));
+    assert_eq!(list.len(), 99);
+    assert_eq!(list.iter().next(), Some(&98));
+
+    arena.unroot(&head);
+    arena.collect();
+    assert!(arena.is_empty());
+}
+
+#[derive(Trace, Clone)]
+struct Account {
+    balance: u32,
+}
+
+#[test]
+fn fold_type_sums_a_field() {
+    let mut arena = Arena::new();
+    for balance in 1..=4 {
+        arena.gc(Account { balance });
+        arena.gc(Object::Simple);
+    }
+    arena.gc_str("not an account");
+    assert_eq!(arena.fold_type(0, |sum, a: &Account| sum + a.balance), 10);
+    assert_eq!(arena.fold_type(0, |n, _: &Object| n + 1), 4);
+}
+
+#[test]
+fn to_graph_lists_nodes_and_edges() {
+    let mut arena = Arena::new();
+    let mut a = arena.gc(Object::Simple);
+    let b = arena.gc(Object::Container(vec![a.clone()]));
+    let c = arena.root(Object::Container(vec![a.clone(), b.clone()]));
+    Gc::replace(&mut a, Object::Container(vec![c.clone()])).unwrap();
+    arena.gc_str("loose");
+    let graph = arena.to_graph();
+    assert_eq!(graph.nodes.len(), 4);
+    let find = |gc: &Gc<Object>| {
+        let ptr = unsafe { (*gc.ptr.get()).inner.unwrap().as_ptr() as *const () };
+        graph.nodes.iter().position(|n| n.ptr.as_ptr() as *const () == ptr).unwrap()
+    };
+    let (a, b, c) = (find(&a), find(&b), find(&c));
+    let mut edges = graph.edges.clone();
+    edges.sort();
+    let mut expected = vec![(a, c), (b, a), (c, a), (c, b)];
+    expected.sort();
+    assert_eq!(edges, expected);
+    assert_eq!(graph.nodes.iter().filter(|n| n.root).count(), 1);
+    assert!(graph.nodes[c].root);
+    assert_eq!(graph.nodes.iter().filter(|n| n.type_id == TypeId::of::<str>()).count(), 1);
+}
+
+struct Unclonable(Account);
+
+impl Clone for Unclonable {
+    fn clone(&self) -> Self {
+        panic!("should have been moved");
+    }
+}
+
+impl Trace for Unclonable {
+    fn trace(&self, _visitor: &Visitor) {}
+}
+
+#[test]
+fn gc_from_rc_moves_unique_values() {
+    let mut arena = Arena::new();
+    let moved = arena.gc_from_rc(Rc::new(Unclonable(Account { balance: 5 })));
+    assert_eq!(moved.0.balance, 5);
+    assert_eq!(arena.len(), 1);
+
+    let shared = Rc::new(Account { balance: 7 });
+    let cloned = arena.gc_from_rc(shared.clone());
+    assert_eq!(cloned.balance, 7);
+    assert_eq!(Rc::strong_count(&shared), 1);
+    arena.collect();
+    assert!(arena.is_empty());
+    assert_eq!(shared.balance, 7);
+}
+
+#[test]
+fn mark_then_sweep_matches_collect() {
+    fn build(arena: &mut Arena) {
+        let child = arena.gc(Object::Simple);
+        arena.root(Object::Container(vec![child]));
+        for _ in 0..5 {
+            arena.gc(Object::Simple);
+        }
+    }
+    let mut whole = Arena::new();
+    build(&mut whole);
+    let col = whole.collect();
+
+    let mut split = Arena::new();
+    build(&mut split);
+    assert_eq!(split.mark(), 2);
+    let swept = split.sweep();
+    assert_eq!((swept.total, swept.collected), (col.total, col.collected));
+    assert_eq!((swept.total, swept.collected), (7, 5));
+    assert_eq!(split.len(), whole.len());
+    assert!(split.iter().all(|t| !unsafe { t.as_ref().marked() }));
+}
+
+#[test]
+fn sweep_marks_again_when_the_marks_are_stale() {
+    let mut arena = Arena::new();
+    // Never marked at all.
+    let a = arena.root(Object::Simple);
+    arena.gc(Object::Simple);
+    assert_eq!(arena.sweep().collected, 1);
+    assert!(Gc::try_as_ref(&a).is_some());
+
+    // Rooted after the mark, freshly or not.
+    let loose = arena.gc(Object::Simple);
+    arena.mark();
+    let b = arena.root(Object::Container(vec![loose.clone()]));
+    let c = arena.gc(Object::Simple);
+    arena.mark();
+    arena.make_root(&c);
+    assert_eq!(arena.sweep().collected, 0);
+    assert!(Gc::try_as_ref(&loose).is_some());
+    assert!(Gc::try_as_ref(&b).is_some());
+    assert!(Gc::try_as_ref(&c).is_some());
+
+    // A SharedGc repointed after the mark.
+    let d = arena.gc(Object::Simple);
+    let shared = arena.gc_shared(Object::Simple);
+    let old = shared.borrow().clone();
+    arena.mark();
+    shared.set(d.clone());
+    assert_eq!(arena.sweep().collected, 1);
+    assert!(Gc::try_as_ref(&d).is_some());
+    assert!(Gc::try_as_ref(&old).is_none());
+    assert_eq!(arena.collect().collected, 0);
+}
+
+#[test]
+fn pinned_gc_keeps_its_address() {
+    use std::marker::PhantomPinned;
+
+    struct SelfAware {
+        value: u32,
+        _pinned: PhantomPinned,
+    }
+
+    impl Trace for SelfAware {
+        fn trace(&self, _visitor: &Visitor) {}
+    }
+
+    let mut arena = Arena::new();
+    let moved = Rc::new(Cell::new(false));
+    let flag = Rc::clone(&moved);
+    arena.register_finalizer(move |_: &mut SelfAware| flag.set(true));
+    let gc = arena.gc(SelfAware { value: 3, _pinned: PhantomPinned });
+    arena.make_root(&gc);
+    // Any other handle could move the value out from under the pin.
+    let other = gc.clone();
+    let gc = Gc::pin(gc).err().unwrap();
+    drop(other);
+    let weak = Gc::downgrade(&gc);
+    let gc = Gc::pin(gc).err().unwrap();
+    drop(weak);
+    let pinned = Gc::pin(gc).ok().unwrap();
+    let before = &*pinned as *const SelfAware;
+    arena.gc(Object::Simple);
+    arena.collect();
+    assert_eq!(&*pinned as *const SelfAware, before);
+    assert_eq!(pinned.value, 3);
+    arena.unroot_pinned(&pinned);
+    assert_eq!(arena.collect().collected, 1);
+    assert!(!moved.get());
+}
+
+#[test]
+fn intern_value_shares_until_evicted() {
+    let mut arena = Arena::new();
+    let ones: Vec<_> = (0..100).map(|_| arena.intern_value(1i64)).collect();
+    assert_eq!(arena.len(), 1);
+    assert!(ones.iter().all(|one| Gc::ptr_eq(one, &ones[0])));
+
+    arena.set_value_intern_limit(2);
+    let two = arena.intern_value(2i64);
+    assert!(Gc::ptr_eq(&arena.intern_value(1i64), &ones[0]));
+    let three = arena.intern_value(3i64);  // evicts 2, the least recently used
+    assert!(Gc::ptr_eq(&arena.intern_value(1i64), &ones[0]));
+    assert!(Gc::ptr_eq(&arena.intern_value(3i64), &three));
+    assert!(!Gc::ptr_eq(&arena.intern_value(2i64), &two));
+    assert_eq!(arena.len(), 4);
+}
+
+#[test]
+fn root_scanner_keeps_objects_for_one_collection() {
+    let mut arena = Arena::new();
+    let stack: Rc<RefCell<Vec<Gc<Object>>>> = Rc::default();
+    let scanned = stack.clone();
+    arena.set_root_scanner(move |visitor| {
+        for gc in scanned.borrow().iter() {
+            visitor.visit(gc);
+        }
+    });
+    let local = arena.gc(Object::Simple);
+    let child = arena.gc(Object::Simple);
+    let frame = arena.gc(Object::Container(vec![child.clone()]));
+    stack.borrow_mut().extend([local.clone(), frame]);
+    arena.collect();
+    assert_eq!(arena.len(), 3);
+    assert!(Gc::try_as_ref(&child).is_some());
+    stack.borrow_mut().clear();
+    arena.collect();
+    assert!(arena.is_empty());
+    assert!(Gc::try_as_ref(&local).is_none());
+}
+
+#[test]
+fn gc_with_layout_aligns_the_value() {
+    let mut arena = Arena::new();
+    let layout = Layout::from_size_align(mem::size_of::<GcBox<Account>>(), 64).unwrap();
+    let gcs: Vec<_> = (0..8).map(|balance| arena.gc_with_layout(Account { balance }, layout)).collect();
+    for (balance, gc) in gcs.iter().enumerate() {
+        assert_eq!(Gc::as_ptr(gc) as usize % 64, 0);
+        assert_eq!(gc.balance as usize, balance);
+    }
+    arena.make_root(&gcs[3]);
+    arena.collect();
+    assert_eq!(arena.len(), 1);
+    assert_eq!(gcs[3].balance, 3);
+}
+
+#[test]
+fn deferred_drops_run_once_later() {
+    fn increment(i: *mut ()) {
+        unsafe {
+            // SAFETY: Points to drops below, which outlives the arena.
+            *(i as *mut usize) += 1;
+        }
+    }
+
+    let mut drops = 0usize;
+    let mut arena = Arena::new();
+    arena.set_defer_drops(true);
+    let gcs: Vec<_> = (0..3).map(|_| arena.gc(Object::RunOnDrop(RunOnDrop {
+        func: increment,
+        data: &mut drops as *mut _ as *mut (),
+    }))).collect();
+    let weak = Gc::downgrade(&gcs[0]);
+    arena.collect();
+    assert!(arena.is_empty());
+    assert!(gcs.iter().all(|gc| Gc::try_as_ref(gc).is_none()));
+    assert!(weak.upgrade().is_none());
+    assert_eq!(drops, 0);
+    assert_eq!(arena.run_deferred_drops(), 3);
+    assert_eq!(drops, 3);
+    assert_eq!(arena.run_deferred_drops(), 0);
+    drop(arena);
+    assert_eq!(drops, 3);
+}
+
+#[test]
+fn external_strong_count_ignores_the_arenas_reference() {
+    let mut arena = Arena::new();
+    let gc = arena.gc(Object::Simple);
+    assert_eq!(Gc::external_strong_count(&gc), 1);
+    let other = gc.clone();
+    assert_eq!(Gc::external_strong_count(&gc), 2);
+    drop(other);
+
+    // Once collected, the arena only hangs on to the alloc while Weaks want it.
+    let weak = Gc::downgrade(&gc);
+    arena.collect();
+    assert_eq!(arena.dead_weak_count(), 1);
+    assert_eq!(Gc::external_strong_count(&gc), 1);
+    drop(weak);
+    arena.collect();
+    assert_eq!(Gc::external_strong_count(&gc), 1);
+}
+
+#[test]
+fn write_barrier_saves_objects_stored_during_incremental_mark() {
+    let mut arena = Arena::new();
+    let leaf = arena.gc(Object::Simple);
+    let mut other = arena.root(Object::Container(vec![leaf.clone()]));
+    let mut holder = arena.root(Object::Container(vec![]));
+    let garbage = arena.gc(Object::Simple);
+    arena.start_incremental_mark();
+    // The last root shaded is the first traced.
+    assert!(!arena.mark_step(1));
+    assert!(!Gc::inspect(&leaf).unwrap().marked);
+    // Move leaf out of what's left to trace and into what's already been traced.
+    Gc::replace(&mut holder, Object::Container(vec![leaf.clone()])).unwrap();
+    arena.write_barrier(&holder, &leaf);
+    Gc::replace(&mut other, Object::Container(vec![])).unwrap();
+    let fresh = arena.gc(Object::Simple);
+    while !arena.mark_step(1) {}
+    let col = arena.sweep();
+    assert_eq!(col.collected, 1);
+    assert!(Gc::try_as_ref(&garbage).is_none());
+    assert!(Gc::try_as_ref(&leaf).is_some());
+    assert!(Gc::try_as_ref(&fresh).is_some());
+    arena.collect();
+    assert_eq!(arena.len(), 3);
+}
+
+#[test]
+fn nested_thread_arenas() {
+    let outer = root(Object::Simple);
+    gc(Object::Simple);
+    push_arena();
+    let inner = root(Object::Container(vec![]));
+    let loose = gc(Object::Simple);
+    assert_eq!(collect().collected, 1);
+    assert!(Gc::try_as_ref(&loose).is_none());
+    assert_eq!(with_arena(|arena| arena.len()), 1);
+    pop_arena();
+    assert!(Gc::try_as_ref(&inner).is_none());
+    assert_eq!(with_arena(|arena| arena.len()), 2);
+    assert_eq!(collect().collected, 1);
+    assert!(Gc::try_as_ref(&outer).is_some());
+}
+
+thread_local! {
+    static CLOSED: Cell<usize> = const { Cell::new(0) };
+}
+
+#[test]
+fn resources_close_once_when_collected() {
+    fn close(fd: i32) {
+        assert_eq!(fd, 7);
+        CLOSED.with(|closed| closed.set(closed.get() + 1));
+    }
+
+    let mut arena = Arena::new();
+    let file = arena.gc_resource(7, close);
+    arena.make_root(&file);
+    arena.collect();
+    assert_eq!(Gc::with_handle(&file, |&fd| fd), Ok(7));
+    assert_eq!(CLOSED.with(Cell::get), 0);
+    arena.unroot(&file);
+    arena.collect();
+    assert_eq!(CLOSED.with(Cell::get), 1);
+    assert_eq!(Gc::with_handle(&file, |&fd| fd), Err(ResourceError::Collected));
+    drop(arena);
+    assert_eq!(CLOSED.with(Cell::get), 1);
+}
+
+#[test]
+fn balanced_work_leaves_no_reachability_diff() {
+    let mut arena = Arena::new();
+    let keep = arena.root(Object::Simple);
+    arena.collect();
+    let before = arena.snapshot();
+    for _ in 0..10 {
+        let temp = arena.root(Object::Container(vec![keep.clone()]));
+        arena.unroot(&temp);
+    }
+    arena.collect();
+    assert!(arena.reachability_diff(&before).is_empty());
+
+    let leaked = arena.root(Object::Simple);
+    arena.collect();
+    let diff = arena.reachability_diff(&before);
+    assert_eq!(diff.new.len(), 1);
+    assert_eq!(diff.new[0].id, Gc::inspect(&leaked).unwrap().generation);
+}
+
+#[test]
+fn max_objects_collects_then_refuses() {
+    let mut arena = Arena::new();
+    arena.set_max_objects(Some(4));
+    for _ in 0..10 {
+        arena.gc_try(Object::Simple).unwrap();
+    }
+    assert!(arena.len() <= 4);
+    assert_eq!(arena.last_collect_reason(), Some(CollectReason::HeapLimit));
+
+    for _ in 0..4 {
+        let obj = arena.gc_try(Object::Simple).unwrap();
+        arena.make_root(&obj);
+    }
+    assert_eq!(arena.gc_try(Object::Simple).err(), Some(HeapLimitExceeded));
+    assert_eq!(arena.len(), 4);
+}
+
+#[test]
+#[should_panic(expected = "heap limit exceeded")]
+fn max_bytes_panics_in_gc() {
+    let mut arena = Arena::new();
+    arena.set_max_bytes(Some(1));
+    arena.gc(Object::Simple);
+}
+
+#[test]
+fn heap_caps_cover_strings() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    let mut arena = Arena::new();
+    arena.set_max_objects(Some(2));
+    let kept = arena.intern("kept");
+    arena.make_root(&kept);
+    for _ in 0..10 {
+        arena.gc_str("garbage");
+    }
+    assert!(arena.len() <= 2);
+    assert_eq!(arena.last_collect_reason(), Some(CollectReason::HeapLimit));
+    let also = arena.gc_str("also kept");
+    arena.make_root(&also);
+    let refused = catch_unwind(AssertUnwindSafe(|| arena.gc_str("one too many")));
+    assert!(refused.is_err());
+    assert_eq!(arena.len(), 2);
+    // Batches count as every object in them.
+    let mut arena = Arena::new();
+    arena.set_max_objects(Some(2));
+    let refused = catch_unwind(AssertUnwindSafe(|| arena.gc_batch(vec![1u8, 2, 3])));
+    assert!(refused.is_err());
+    assert!(arena.is_empty());
+}
+
+#[test]
+fn as_ref_unchecked_reads_live_objects() {
+    let mut arena = Arena::new();
+    let obj = arena.root(Object::Simple);
+    arena.collect();
+    assert_eq!(unsafe { Gc::as_ref_unchecked(&obj) }, &Object::Simple);
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "as_ref_unchecked on collected object")]
+fn as_ref_unchecked_asserts_in_debug() {
+    let mut arena = Arena::new();
+    let obj = arena.gc(Object::Simple);
+    arena.collect();
+    unsafe { Gc::as_ref_unchecked(&obj) };
+}
+
+#[test]
+fn unroot_from_the_middle_keeps_the_rest() {
+    let mut arena = Arena::new();
+    let objs: Vec<_> = (0..5).map(|_| arena.root(Object::Simple)).collect();
+    arena.unroot(&objs[1]);
+    arena.unroot(&objs[1]);
+    arena.make_root(&objs[3]);
+    assert_eq!(arena.roots_len(), 4);
+    assert!(!Gc::inspect(&objs[1]).unwrap().rooted);
+    assert!(Gc::inspect(&objs[4]).unwrap().rooted);
+    arena.optimize_root_order();
+    arena.unroot(&objs[4]);
+    arena.unroot(&objs[0]);
+    let col = arena.collect();
+    assert_eq!(col.collected, 3);
+    assert!(Gc::try_as_ref(&objs[2]).is_some());
+    assert!(Gc::try_as_ref(&objs[3]).is_some());
+    arena.unroot(&objs[2]);
+    arena.unroot(&objs[3]);
+    assert_eq!(arena.roots_len(), 0);
+}
+
+#[test]
+fn std_containers_trace_their_contents() {
+    let mut arena = Arena::new();
+    let items: Vec<_> = (0..6).map(|_| arena.gc(Object::Simple)).collect();
+    let mut by_name = HashMap::new();
+    by_name.insert("a", items[2].clone());
+    let mut by_order = BTreeMap::new();
+    by_order.insert(1, Some(items[3].clone()));
+    let list = arena.root(vec![items[0].clone(), items[1].clone()]);
+    let misc = arena.root((by_name, by_order, Box::new(items[4].clone()), None::<Gc<Object>>));
+    arena.collect();
+    assert!(items[..5].iter().all(|gc| Gc::try_as_ref(gc).is_some()));
+    assert!(Gc::try_as_ref(&items[5]).is_none());
+    arena.unroot(&list);
+    arena.unroot(&misc);
+    arena.collect();
+    assert!(arena.is_empty());
+    assert!(items.iter().all(|gc| Gc::try_as_ref(gc).is_none()));
+}
+
+#[test]
+fn long_chains_mark_without_recursing() {
+    let mut arena = Arena::new();
+    let mut head = arena.gc(Object::Simple);
+    for _ in 0..100_000 {
+        head = arena.gc(Object::Container(vec![head]));
+    }
+    arena.make_root(&head);
+    let col = arena.collect();
+    assert_eq!(col.collected, 0);
+    assert_eq!(arena.len(), 100_001);
+    arena.unroot(&head);
+    let col = arena.collect();
+    assert_eq!(col.collected, 100_001);
+}
+
+#[test]
+fn len_is_kept_up_to_date() {
+    let mut arena = Arena::new();
+    assert!(arena.is_empty());
+    assert_eq!(arena.len(), 0);
+    let mut kept = Vec::new();
+    for round in 1..=3 {
+        kept.push(arena.root(Object::Simple));
+        arena.gc(Object::Simple);
+        arena.gc_batch(vec![Object::Simple, Object::Simple]);
+        assert_eq!(arena.len(), round + 3);
+        assert_eq!(arena.len(), arena.iter().count());
+        arena.collect();
+        assert_eq!(arena.len(), round);
+        assert_eq!(arena.roots_len(), round);
+    }
+    let detached = kept.pop().unwrap();
+    arena.detach(&detached);
+    assert_eq!(arena.len(), 2);
+    arena.adopt(&detached);
+    assert_eq!(arena.len(), 3);
+    let split = arena.split_off_unreachable();
+    assert_eq!((arena.len(), split.len()), (2, 1));
+}
+
+#[cfg(feature = "serde")]
+#[derive(Trace, serde::Serialize, serde::Deserialize)]
+struct Node {
+    label: u32,
+    edges: Vec<Gc<Node>>,
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_keeps_sharing_and_cycles() {
+    let mut arena = Arena::new();
+    // A diamond, top to bottom, with bottom pointing back up at top.
+    let mut bottom = arena.gc(Node { label: 3, edges: Vec::new() });
+    let left = arena.gc(Node { label: 1, edges: vec![bottom.clone()] });
+    let right = arena.gc(Node { label: 2, edges: vec![bottom.clone()] });
+    let top = arena.root(Node { label: 0, edges: vec![left, right] });
+    Gc::replace(&mut bottom, Node { label: 3, edges: vec![top.clone()] }).unwrap();
+
+    let mut json = Vec::new();
+    serialize_graph(&top, &mut serde_json::Serializer::new(&mut json)).unwrap();
+    assert_eq!(String::from_utf8_lossy(&json).matches("label").count(), 4);
+
+    let mut copy_arena = Arena::new();
+    let mut de = serde_json::Deserializer::from_slice(&json);
+    let copy: Gc<Node> = deserialize_graph(&mut copy_arena, &mut de).unwrap();
+    copy_arena.make_root(&copy);
+    assert_eq!(copy_arena.collect().collected, 0);
+    assert_eq!(copy_arena.len(), 4);
+    let (left, right) = (&copy.edges[0], &copy.edges[1]);
+    assert_eq!((copy.label, left.label, right.label), (0, 1, 2));
+    assert!(Gc::ptr_eq(&left.edges[0], &right.edges[0]));
+    assert_eq!(left.edges[0].label, 3);
+    assert!(Gc::ptr_eq(&left.edges[0].edges[0], &copy));
+
+    // Once nothing roots it, the copy is garbage like any other.
+    copy_arena.unroot(&copy);
+    assert_eq!(copy_arena.collect().collected, 4);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_refuses_references_to_nothing() {
+    let mut arena = Arena::new();
+    let mut de = serde_json::Deserializer::from_str("[0, null]");
+    assert!(deserialize_graph::<Gc<Node>, _>(&mut arena, &mut de).is_err());
+    let mut de = serde_json::Deserializer::from_str("[0, {\"label\": 0, \"edges\": [[0, null]]}]");
+    let node = deserialize_graph::<Gc<Node>, _>(&mut arena, &mut de).unwrap();
+    assert!(Gc::ptr_eq(&node, &node.edges[0]));
+}
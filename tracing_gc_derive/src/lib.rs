@@ -0,0 +1,307 @@
+// #[derive(Trace)] for tracing_gc. Items are simple enough to pick apart by hand, so this goes
+// without syn: every field of a struct, or of each enum variant, gets traced in turn, except those
+// marked #[trace(skip)]. Type parameters all get a Trace bound.
+
+extern crate proc_macro;
+
+use proc_macro::{Delimiter, Spacing, TokenStream, TokenTree};
+use std::iter::Peekable;
+
+// Along with whether each field is traced.
+enum Fields {
+    Named(Vec<(String, bool)>),
+    Unnamed(Vec<bool>),
+    Unit,
+}
+
+// What goes between impl and the type, after the type's name, and after where.
+struct Generics {
+    params: String,
+    args: String,
+    bounds: Vec<String>,
+}
+
+#[proc_macro_derive(Trace, attributes(trace))]
+pub fn derive_trace(input: TokenStream) -> TokenStream {
+    match expand(input) {
+        Ok(out) => out,
+        Err(msg) => format!("compile_error!({:?});", msg),
+    }.parse().unwrap()
+}
+
+fn expand(input: TokenStream) -> Result<String, String> {
+    let mut tokens = input.into_iter().peekable();
+    attrs(&mut tokens)?;
+    skip_vis(&mut tokens);
+    let kind = ident(&mut tokens).ok_or("expected struct or enum")?;
+    let name = ident(&mut tokens).ok_or("expected a type name")?;
+    let mut generics = generics(&mut tokens);
+    generics.bounds.extend(where_clause(&mut tokens));
+    let body = match kind.as_str() {
+        "struct" => {
+            let fields = match tokens.next() {
+                Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Brace => named(g.stream())?,
+                Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Parenthesis => unnamed(g.stream())?,
+                _ => Fields::Unit,
+            };
+            // A tuple struct's where clause comes after its fields.
+            generics.bounds.extend(where_clause(&mut tokens));
+            let access: Vec<String> = match fields {
+                Fields::Named(names) => names.iter()
+                    .filter(|(_, traced)| *traced)
+                    .map(|(f, _)| format!("&self.{}", f))
+                    .collect(),
+                Fields::Unnamed(traced) => (0..traced.len())
+                    .filter(|&i| traced[i])
+                    .map(|i| format!("&self.{}", i))
+                    .collect(),
+                Fields::Unit => Vec::new(),
+            };
+            trace_all(&access)
+        },
+        "enum" => {
+            let variants = match tokens.next() {
+                Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Brace => g.stream(),
+                _ => return Err("expected enum variants".into()),
+            };
+            let arms: Vec<String> = split_commas(variants).into_iter()
+                .map(|variant| arm(&name, variant))
+                .collect::<Result<_, _>>()?;
+            if arms.is_empty() {
+                "match *self {}".into()
+            } else {
+                format!("match self {{ {} }}", arms.concat())
+            }
+        },
+        _ => return Err(format!("derive(Trace) doesn't support {} items", kind)),
+    };
+    let bounds = if generics.bounds.is_empty() {
+        String::new()
+    } else {
+        format!("where {}", generics.bounds.join(", "))
+    };
+    Ok(format!(
+        "impl<{}> ::tracing_gc::Trace for {}<{}> {} {{ \
+            #[allow(unused_variables)] \
+            fn trace(&self, visitor: &::tracing_gc::Visitor) {{ {} }} \
+        }}",
+        generics.params, name, generics.args, bounds, body,
+    ))
+}
+
+fn generics<I: Iterator<Item = TokenTree>>(tokens: &mut Peekable<I>) -> Generics {
+    let mut generics = Generics {
+        params: String::new(),
+        args: String::new(),
+        bounds: Vec::new(),
+    };
+    if !is_punct(tokens.peek(), '<') {
+        return generics;
+    }
+    tokens.next();
+    // Everything up to the matching '>'.
+    let mut inner = Vec::new();
+    let mut depth = 0usize;
+    for token in tokens.by_ref() {
+        if let TokenTree::Punct(p) = &token {
+            match p.as_char() {
+                '<' => depth += 1,
+                '>' if depth == 0 => break,
+                '>' => depth -= 1,
+                _ => (),
+            }
+        }
+        inner.push(token);
+    }
+    let mut params = Vec::new();
+    let mut args = Vec::new();
+    for param in split_commas(inner.into_iter().collect()) {
+        let param = strip_default(param);
+        let arg = match &param[..] {
+            // A lifetime is a quote joined to an ident.
+            [quote @ TokenTree::Punct(p), name, ..] if p.as_char() == '\'' => {
+                vec![quote.clone(), name.clone()]
+            },
+            [TokenTree::Ident(c), name, ..] if c.to_string() == "const" => vec![name.clone()],
+            [name, ..] => {
+                generics.bounds.push(format!("{}: ::tracing_gc::Trace", name));
+                vec![name.clone()]
+            },
+            [] => continue,
+        };
+        params.push(stream(param));
+        args.push(stream(arg));
+    }
+    generics.params = params.join(", ");
+    generics.args = args.join(", ");
+    generics
+}
+
+// The predicates of a where clause, if there is one, leaving the body (or the ';') next.
+fn where_clause<I: Iterator<Item = TokenTree>>(tokens: &mut Peekable<I>) -> Vec<String> {
+    match tokens.peek() {
+        Some(TokenTree::Ident(i)) if i.to_string() == "where" => (),
+        _ => return Vec::new(),
+    }
+    tokens.next();
+    let mut clause = Vec::new();
+    while let Some(token) = tokens.peek() {
+        match token {
+            TokenTree::Group(g) if g.delimiter() == Delimiter::Brace => break,
+            TokenTree::Punct(p) if p.as_char() == ';' => break,
+            _ => clause.push(tokens.next().unwrap()),
+        }
+    }
+    split_commas(clause.into_iter().collect()).into_iter().map(stream).collect()
+}
+
+// Drops a generic parameter's default (the '=' and all after it, outside any angle brackets),
+// which can't be repeated in an impl.
+fn strip_default(param: Vec<TokenTree>) -> Vec<TokenTree> {
+    let mut depth = 0usize;
+    let end = param.iter().position(|token| match token {
+        TokenTree::Punct(p) => match p.as_char() {
+            '<' => { depth += 1; false },
+            '>' => { depth = depth.saturating_sub(1); false },
+            '=' => depth == 0 && p.spacing() == Spacing::Alone,
+            _ => false,
+        },
+        _ => false,
+    }).unwrap_or(param.len());
+    param.into_iter().take(end).collect()
+}
+
+fn stream(tokens: Vec<TokenTree>) -> String {
+    tokens.into_iter().collect::<TokenStream>().to_string()
+}
+
+// One match arm, binding the variant's fields to __field0, __field1, ... so they can't shadow the
+// visitor.
+fn arm(name: &str, variant: Vec<TokenTree>) -> Result<String, String> {
+    let mut tokens = variant.into_iter().peekable();
+    attrs(&mut tokens)?;
+    let variant = ident(&mut tokens).ok_or("expected a variant name")?;
+    let fields = match tokens.next() {
+        Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Brace => named(g.stream())?,
+        Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Parenthesis => unnamed(g.stream())?,
+        _ => Fields::Unit,  // possibly with a discriminant, which doesn't matter here
+    };
+    // Skipped fields are bound all the same, and just not traced.
+    let (pattern, traced) = match fields {
+        Fields::Named(names) => {
+            let pairs: Vec<String> = names.iter().enumerate()
+                .map(|(i, (f, _))| format!("{}: __field{}", f, i))
+                .collect();
+            (format!("{{ {} }}", pairs.join(", ")), names.into_iter().map(|(_, t)| t).collect())
+        },
+        Fields::Unnamed(traced) => {
+            let bindings: Vec<String> = (0..traced.len()).map(|i| format!("__field{}", i)).collect();
+            (format!("({})", bindings.join(", ")), traced)
+        },
+        Fields::Unit => (String::new(), Vec::new()),
+    };
+    let bindings: Vec<String> = (0..traced.len())
+        .filter(|&i| traced[i])
+        .map(|i| format!("__field{}", i))
+        .collect();
+    Ok(format!("{}::{} {} => {{ {} }}", name, variant, pattern, trace_all(&bindings)))
+}
+
+fn trace_all(exprs: &[String]) -> String {
+    exprs.iter()
+        .map(|e| format!("::tracing_gc::Trace::trace({}, visitor);", e))
+        .collect()
+}
+
+fn named(stream: TokenStream) -> Result<Fields, String> {
+    let mut fields = Vec::new();
+    for field in split_commas(stream) {
+        let mut tokens = field.into_iter().peekable();
+        let traced = !attrs(&mut tokens)?;
+        skip_vis(&mut tokens);
+        if let Some(name) = ident(&mut tokens) {
+            fields.push((name, traced));
+        }
+    }
+    Ok(Fields::Named(fields))
+}
+
+fn unnamed(stream: TokenStream) -> Result<Fields, String> {
+    split_commas(stream).into_iter()
+        .map(|field| attrs(&mut field.into_iter().peekable()).map(|skip| !skip))
+        .collect::<Result<_, _>>()
+        .map(Fields::Unnamed)
+}
+
+// Splits on commas outside any brackets--including angle brackets, which (unlike the others)
+// aren't grouped by the tokenizer. Empty pieces, as from a trailing comma, are dropped.
+fn split_commas(stream: TokenStream) -> Vec<Vec<TokenTree>> {
+    let mut pieces = vec![Vec::new()];
+    let mut depth = 0usize;
+    let mut arrow = false;  // a '-' just before, so a '>' is part of "->"
+    for token in stream {
+        if let TokenTree::Punct(p) = &token {
+            match p.as_char() {
+                ',' if depth == 0 => {
+                    pieces.push(Vec::new());
+                    continue;
+                },
+                '<' => depth += 1,
+                '>' if !arrow => depth = depth.saturating_sub(1),
+                _ => (),
+            }
+            arrow = p.as_char() == '-' && p.spacing() == Spacing::Joint;
+        } else {
+            arrow = false;
+        }
+        pieces.last_mut().unwrap().push(token);
+    }
+    pieces.retain(|piece| !piece.is_empty());
+    pieces
+}
+
+// Skips over any attributes, returning whether one of them was #[trace(skip)].
+fn attrs<I: Iterator<Item = TokenTree>>(tokens: &mut Peekable<I>) -> Result<bool, String> {
+    let mut skip = false;
+    while is_punct(tokens.peek(), '#') {
+        tokens.next();
+        let attr = match tokens.next() {
+            Some(TokenTree::Group(g)) => g.stream().into_iter().collect::<Vec<_>>(),
+            _ => continue,
+        };
+        match &attr[..] {
+            [TokenTree::Ident(name), TokenTree::Group(args)] if name.to_string() == "trace" => {
+                if args.stream().to_string() != "skip" {
+                    return Err(format!("unknown trace attribute: trace({})", args.stream()));
+                }
+                skip = true;
+            },
+            _ => (),
+        }
+    }
+    Ok(skip)
+}
+
+fn skip_vis<I: Iterator<Item = TokenTree>>(tokens: &mut Peekable<I>) {
+    if let Some(TokenTree::Ident(i)) = tokens.peek() {
+        if i.to_string() == "pub" {
+            tokens.next();
+            if let Some(TokenTree::Group(g)) = tokens.peek() {
+                if g.delimiter() == Delimiter::Parenthesis {
+                    tokens.next();
+                }
+            }
+        }
+    }
+}
+
+fn ident<I: Iterator<Item = TokenTree>>(tokens: &mut Peekable<I>) -> Option<String> {
+    match tokens.next() {
+        Some(TokenTree::Ident(i)) => Some(i.to_string()),
+        _ => None,
+    }
+}
+
+fn is_punct(token: Option<&TokenTree>, c: char) -> bool {
+    matches!(token, Some(TokenTree::Punct(p)) if p.as_char() == c)
+}